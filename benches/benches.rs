@@ -28,6 +28,7 @@ fn bench_create_and_delete_buffers(b: &mut Bencher) {
                 "buffer.new",
                 &plugin::buffer::new::Request {
                     content: Some("bli\nbla\nblub".into()),
+                    uri: None,
                 },
             )
             .unwrap()
@@ -70,11 +70,11 @@ impl SwiboeGtkGui {
         let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0).unwrap();
 
 
-        let cursor_id = {
+        let (buffer_view_id, cursor_id) = {
             let mut buffer_views = gui.buffer_views.write().unwrap();
             let buffer_view = buffer_views.get_or_create(0);
-            let cursor_id = buffer_view.cursor.id().to_string();
-            cursor_id
+            let cursor_id = buffer_view.cursors[0].id().to_string();
+            (buffer_view.id().to_string(), cursor_id)
         };
 
         // NOCOM(#sirver): rename BufferView to Editor(View)?
@@ -107,6 +107,21 @@ impl SwiboeGtkGui {
                 if let Some(name_str) = gdk::keyval_name(key.keyval) {
                     println!("#sirver name_str: {:#?}", name_str);
                     println!("#sirver keypress: {}", time::precise_time_ns());
+                    // We must not hold the lock while calling, the buffer views answer our calls.
+                    let (position, last_cursor_id, last_position, num_cursors) = {
+                        let buffer_views = buffers_clone.read().unwrap();
+                        let buffer_view = buffer_views.get(&buffer_view_id).unwrap();
+                        let last_cursor = buffer_view.cursors.last().unwrap();
+                        (buffer_view.cursors[0].position, last_cursor.id().to_string(),
+                         last_cursor.position, buffer_view.cursors.len())
+                    };
+                    let text = match &name_str as &str {
+                        "Return" => Some("\n".to_string()),
+                        "Tab" => Some("\t".to_string()),
+                        "space" => Some(" ".to_string()),
+                        name if name.chars().count() == 1 => Some(name.to_string()),
+                        _ => None,
+                    };
                     match &name_str as &str {
                         "F2" => {
                             let mut rpc = thin_client.call("buffer.open", &plugin::buffer::open::Request {
@@ -144,30 +159,84 @@ impl SwiboeGtkGui {
                             });
                         },
                         "Up" => {
-                            thin_client.call("gui.buffer_view.move_cursor", &buffer_views::MoveCursorRequest {
+                            let mut rpc = thin_client.call("gui.buffer_view.move_cursor", &buffer_views::MoveCursorRequest {
                                 cursor_id: cursor_id.clone(),
                                 delta: buffer_views::Position { line_index: -1, column_index: 0, },
+                                extend_selection: false,
                             });
+                            rpc.wait().unwrap();
                         },
                         "Down" => {
-                            thin_client.call("gui.buffer_view.move_cursor", &buffer_views::MoveCursorRequest {
+                            let mut rpc = thin_client.call("gui.buffer_view.move_cursor", &buffer_views::MoveCursorRequest {
                                 cursor_id: cursor_id.clone(),
                                 delta: buffer_views::Position { line_index: 1, column_index: 0, },
+                                extend_selection: false,
                             });
+                            rpc.wait().unwrap();
                         }
                         "Left" => {
-                            thin_client.call("gui.buffer_view.move_cursor", &buffer_views::MoveCursorRequest {
+                            let mut rpc = thin_client.call("gui.buffer_view.move_cursor", &buffer_views::MoveCursorRequest {
                                 cursor_id: cursor_id.clone(),
                                 delta: buffer_views::Position { line_index: 0, column_index: -1, },
+                                extend_selection: false,
                             });
+                            rpc.wait().unwrap();
                         },
                         "Right" => {
-                            thin_client.call("gui.buffer_view.move_cursor", &buffer_views::MoveCursorRequest {
+                            let mut rpc = thin_client.call("gui.buffer_view.move_cursor", &buffer_views::MoveCursorRequest {
                                 cursor_id: cursor_id.clone(),
                                 delta: buffer_views::Position { line_index: 0, column_index: 1, },
+                                extend_selection: false,
                             });
+                            rpc.wait().unwrap();
+                        },
+                        "Home" | "End" => {
+                            let column_index = if name_str == "Home" { 0 } else { isize::max_value() };
+                            let mut rpc = thin_client.call("gui.buffer_view.set_cursor", &buffer_views::SetCursorRequest {
+                                cursor_id: cursor_id.clone(),
+                                position: buffer_views::Position { line_index: position.line_index, column_index: column_index, },
+                                extend_selection: false,
+                            });
+                            rpc.wait().unwrap();
+                        },
+                        // Adds a cursor below the last one.
+                        "F5" => {
+                            let mut rpc = thin_client.call("gui.buffer_view.add_cursor", &buffer_views::AddCursorRequest {
+                                buffer_view_id: buffer_view_id.clone(),
+                                position: buffer_views::Position {
+                                    line_index: last_position.line_index + 1,
+                                    column_index: last_position.column_index,
+                                },
+                            });
+                            rpc.wait().unwrap();
+                        },
+                        "F6" => {
+                            if num_cursors > 1 {
+                                let mut rpc = thin_client.call("gui.buffer_view.remove_cursor", &buffer_views::RemoveCursorRequest {
+                                    cursor_id: last_cursor_id,
+                                });
+                                rpc.wait().unwrap();
+                            }
+                        },
+                        "Escape" => {
+                            let mut rpc = thin_client.call("gui.buffer_view.clear_cursors", &buffer_views::ClearCursorsRequest {
+                                buffer_view_id: buffer_view_id.clone(),
+                            });
+                            rpc.wait().unwrap();
+                        },
+                        "BackSpace" => {
+                            let mut rpc = thin_client.call("gui.buffer_view.delete", &buffer_views::DeleteRequest {
+                                buffer_view_id: buffer_view_id.clone(),
+                            });
+                            rpc.wait().unwrap();
+                        },
+                        _ => if let Some(text) = text {
+                            let mut rpc = thin_client.call("gui.buffer_view.insert", &buffer_views::InsertRequest {
+                                buffer_view_id: buffer_view_id.clone(),
+                                text: text,
+                            });
+                            rpc.wait().unwrap();
                         },
-                        _ => (),
                     // // if let Some(button) = shortcuts.get(&name_str) {
                         // // button.clicked();
                         // return signal::Inhibit(true);
@@ -93,9 +93,9 @@ fn draw(buffer_view: &BufferView, inner_size: cairo::RectangleInt, cr: cairo::Co
 
     let font_extents = cr.font_extents();
 
-    // Draw the cursor.
-    {
-        let position = buffer_view.cursor.position;
+    // Draw the cursors.
+    for cursor in &buffer_view.cursors {
+        let position = cursor.position;
         let (x, y, c) = match buffer_view.lines.get(position.line_index as usize) {
             Some(line) => {
                 let y = (position.line_index - buffer_view.top_line_index) as f64 * font_extents.height;
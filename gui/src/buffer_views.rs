@@ -19,10 +19,19 @@ use swiboe::rpc;
 use swiboe;
 use uuid::Uuid;
 
-// NOCOM(#sirver): nothing in this file is tested .
 #[derive(Debug)]
 pub enum BufferViewError {
     UnknownCursor,
+    UnknownBufferView,
+    PrimaryCursor,
+    Rpc(rpc::Error),
+    Swiboe(swiboe::Error),
+}
+
+impl From<swiboe::Error> for BufferViewError {
+    fn from(error: swiboe::Error) -> Self {
+        BufferViewError::Swiboe(error)
+    }
 }
 
 impl From<BufferViewError> for rpc::Error {
@@ -31,6 +40,10 @@ impl From<BufferViewError> for rpc::Error {
 
          let (kind, details) = match error {
              BufferViewError::UnknownCursor => (InvalidArgs, format!("unknown_cursor")),
+             BufferViewError::UnknownBufferView => (InvalidArgs, format!("unknown_buffer_view")),
+             BufferViewError::PrimaryCursor => (InvalidArgs, format!("primary_cursor")),
+             BufferViewError::Rpc(error) => return error,
+             BufferViewError::Swiboe(error) => (Io, format!("{}", error)),
          };
 
          rpc::Error {
@@ -66,7 +79,7 @@ impl client::rpc::server::Rpc for Scroll {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Position {
     /// 0 based line index into the buffer.
     pub line_index: isize,
@@ -86,34 +99,101 @@ impl ops::Add for Position {
     }
 }
 
+impl Position {
+    fn to_buffer_position(&self) -> plugin::buffer::Position {
+        plugin::buffer::Position::LineColumn {
+            line_index: self.line_index as usize,
+            column_index: self.column_index as usize,
+        }
+    }
+
+    // Where this position ends up after 'change' was applied to the buffer. Positions inside of
+    // the replaced text move to the end of the new text.
+    fn adjusted(&self, change: &plugin::buffer::BufferChanged) -> Position {
+        let start = Position {
+            line_index: change.start.line_index as isize,
+            column_index: change.start.column_index as isize,
+        };
+        let end = Position {
+            line_index: change.end.line_index as isize,
+            column_index: change.end.column_index as isize,
+        };
+        if *self < start {
+            return *self;
+        }
+
+        let new_end = match change.text.rfind('\n') {
+            None => Position {
+                line_index: start.line_index,
+                column_index: start.column_index + change.text.chars().count() as isize,
+            },
+            Some(index) => Position {
+                line_index: start.line_index + change.text.matches('\n').count() as isize,
+                column_index: change.text[index + 1..].chars().count() as isize,
+            },
+        };
+        if *self <= end {
+            new_end
+        } else if self.line_index == end.line_index {
+            Position {
+                line_index: new_end.line_index,
+                column_index: new_end.column_index + self.column_index - end.column_index,
+            }
+        } else {
+            Position {
+                line_index: self.line_index + new_end.line_index - end.line_index,
+                column_index: self.column_index,
+            }
+        }
+    }
+}
+
+/// The id of the first cursor of the view 'buffer_view_id'. It never changes, so GUIs can hand it to
+/// config files before the view is drawn.
+pub fn primary_cursor_id(buffer_view_id: &str) -> String {
+    format!("{}-primary", buffer_view_id)
+}
+
 pub struct Cursor {
     id: String,
     pub wanted_position: Position,
     pub position: Position,
+    /// The other end of the selection. The same as 'position' if nothing is selected.
+    pub anchor: Position,
 }
 
 impl Cursor {
-    pub fn new() -> Self {
+    pub fn new(position: Position) -> Self {
         Cursor {
-            id: "every_cursor_currently_has_this_id".into(),
-            // NOCOM(#sirver): bring back
-            // id: Uuid::new_v4().to_hyphenated_string(),
-            wanted_position: Position { line_index: 0, column_index: 0 },
-            position: Position { line_index: 0, column_index: 0 },
+            id: Uuid::new_v4().to_hyphenated_string(),
+            wanted_position: position,
+            position: position,
+            anchor: position,
         }
     }
 
     pub fn id(&self) -> &str {
         &self.id
     }
+
+    /// The selected range in document order.
+    pub fn selection(&self) -> (Position, Position) {
+        (cmp::min(self.anchor, self.position), cmp::max(self.anchor, self.position))
+    }
+
+    pub fn has_selection(&self) -> bool {
+        self.anchor != self.position
+    }
 }
 
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct MoveCursorRequest {
     pub cursor_id: String,
-    // NOCOM(#sirver): this could also be absolute or so.
     pub delta: Position,
+    /// If set, the anchor stays where it is and the selection grows or shrinks.
+    #[serde(default)]
+    pub extend_selection: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -129,14 +209,220 @@ impl client::rpc::server::Rpc for MoveCursor {
 
         let mut buffer_views = self.buffer_views.write().unwrap();
 
-        try_rpc!(context, buffer_views.move_cursor(&request.cursor_id, request.delta));
+        try_rpc!(context, buffer_views.move_cursor(&request.cursor_id, request.delta, request.extend_selection));
         context.finish(rpc::Result::success(MoveCursorResponse)).unwrap();
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SetCursorRequest {
+    pub cursor_id: String,
+    pub position: Position,
+    /// If set, the anchor stays where it is and the selection grows or shrinks.
+    #[serde(default)]
+    pub extend_selection: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SetCursorResponse;
+
+struct SetCursor {
+    buffer_views: Arc<RwLock<BufferViews>>,
+}
+
+impl client::rpc::server::Rpc for SetCursor {
+    fn call(&self,  mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: SetCursorRequest = try_rpc!(context, serde_json::from_value(args));
+
+        let mut buffer_views = self.buffer_views.write().unwrap();
+
+        try_rpc!(context, buffer_views.set_cursor(&request.cursor_id, request.position, request.extend_selection));
+        context.finish(rpc::Result::success(SetCursorResponse)).unwrap();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct AddCursorRequest {
+    pub buffer_view_id: String,
+    pub position: Position,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct AddCursorResponse {
+    pub cursor_id: String,
+}
+
+struct AddCursor {
+    buffer_views: Arc<RwLock<BufferViews>>,
+}
+
+impl client::rpc::server::Rpc for AddCursor {
+    fn call(&self,  mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: AddCursorRequest = try_rpc!(context, serde_json::from_value(args));
+
+        let mut buffer_views = self.buffer_views.write().unwrap();
+
+        let cursor_id = try_rpc!(context, buffer_views.add_cursor(&request.buffer_view_id, request.position));
+        context.finish(rpc::Result::success(AddCursorResponse {
+            cursor_id: cursor_id,
+        })).unwrap();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct RemoveCursorRequest {
+    pub cursor_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct RemoveCursorResponse;
+
+struct RemoveCursor {
+    buffer_views: Arc<RwLock<BufferViews>>,
+}
+
+impl client::rpc::server::Rpc for RemoveCursor {
+    fn call(&self,  mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: RemoveCursorRequest = try_rpc!(context, serde_json::from_value(args));
+
+        let mut buffer_views = self.buffer_views.write().unwrap();
+
+        try_rpc!(context, buffer_views.remove_cursor(&request.cursor_id));
+        context.finish(rpc::Result::success(RemoveCursorResponse)).unwrap();
+    }
+}
+
+/// Removes all cursors but the primary one and its selection.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ClearCursorsRequest {
+    pub buffer_view_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ClearCursorsResponse;
+
+struct ClearCursors {
+    buffer_views: Arc<RwLock<BufferViews>>,
+}
+
+impl client::rpc::server::Rpc for ClearCursors {
+    fn call(&self,  mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: ClearCursorsRequest = try_rpc!(context, serde_json::from_value(args));
+
+        let mut buffer_views = self.buffer_views.write().unwrap();
+
+        try_rpc!(context, buffer_views.clear_cursors(&request.buffer_view_id));
+        context.finish(rpc::Result::success(ClearCursorsResponse)).unwrap();
+    }
+}
+
+/// Replaces the selection of every cursor with 'text'. Cursors without a selection insert.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct InsertRequest {
+    pub buffer_view_id: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct InsertResponse;
+
+struct Insert {
+    buffer_views: Arc<RwLock<BufferViews>>,
+}
+
+impl client::rpc::server::Rpc for Insert {
+    fn call(&self,  mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: InsertRequest = try_rpc!(context, serde_json::from_value(args));
+
+        // The ranges come from the cursors as we show them, so the changes of the previous edit
+        // must have reached us already. They do, since the buffer broadcasts them before it
+        // answers and the client runs our RPCs one at a time in the order they arrive (see
+        // 'ThreadPool::new(1)' in the client's rpc_loop). Running them in parallel breaks this.
+        // The buffer tells us about the changes while we call it, so we must not hold the lock.
+        let (mut client, buffer_index, ranges) = {
+            let buffer_views = self.buffer_views.read().unwrap();
+            let (buffer_index, ranges) = try_rpc!(context, buffer_views.insert_ranges(&request.buffer_view_id));
+            (buffer_views.client.clone(), buffer_index, ranges)
+        };
+        try_rpc!(context, replace_ranges(&mut client, buffer_index, ranges, &request.text));
+        context.finish(rpc::Result::success(InsertResponse)).unwrap();
+    }
+}
+
+/// Deletes the selection of every cursor. Cursors without a selection delete the character before
+/// them.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DeleteRequest {
+    pub buffer_view_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DeleteResponse;
+
+struct Delete {
+    buffer_views: Arc<RwLock<BufferViews>>,
+}
+
+impl client::rpc::server::Rpc for Delete {
+    fn call(&self,  mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: DeleteRequest = try_rpc!(context, serde_json::from_value(args));
+
+        // Relies on the same order of calls as 'Insert'.
+        // The buffer tells us about the changes while we call it, so we must not hold the lock.
+        let (mut client, buffer_index, ranges) = {
+            let buffer_views = self.buffer_views.read().unwrap();
+            let (buffer_index, ranges) = try_rpc!(context, buffer_views.delete_ranges(&request.buffer_view_id));
+            (buffer_views.client.clone(), buffer_index, ranges)
+        };
+        try_rpc!(context, replace_ranges(&mut client, buffer_index, ranges, ""));
+        context.finish(rpc::Result::success(DeleteResponse)).unwrap();
+    }
+}
+
+struct OnBufferChanged {
+    buffer_views: Arc<RwLock<BufferViews>>,
+}
+
+impl client::rpc::server::Rpc for OnBufferChanged {
+    fn call(&self,  mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let change: plugin::buffer::BufferChanged = try_rpc!(context, serde_json::from_value(args));
+
+        let (needs_reload, mut client) = {
+            let mut buffer_views = self.buffer_views.write().unwrap();
+            (buffer_views.on_buffer_changed(&change), buffer_views.client.clone())
+        };
+        if needs_reload {
+            let response = try_rpc!(context, get_content(&mut client, change.buffer_index));
+            let mut buffer_views = self.buffer_views.write().unwrap();
+            buffer_views.reload(change.buffer_index, &response.content, response.revision);
+        }
+        context.finish(rpc::Result::success(())).unwrap();
+    }
+}
+
+struct OnBufferReloaded {
+    buffer_views: Arc<RwLock<BufferViews>>,
+}
+
+impl client::rpc::server::Rpc for OnBufferReloaded {
+    fn call(&self,  mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let reloaded: plugin::buffer::BufferReloaded = try_rpc!(context, serde_json::from_value(args));
+
+        let mut client = self.buffer_views.read().unwrap().client.clone();
+        let response = try_rpc!(context, get_content(&mut client, reloaded.buffer_index));
+        let mut buffer_views = self.buffer_views.write().unwrap();
+        buffer_views.reload(reloaded.buffer_index, &response.content, response.revision);
+        context.finish(rpc::Result::success(())).unwrap();
+    }
+}
+
 pub struct BufferView {
     id: String,
-    pub cursor: Cursor,
+    buffer_index: usize,
+    /// The revision of the buffer that 'lines' shows.
+    revision: u64,
+    /// Never empty. The first cursor is the primary one, it cannot be removed.
+    pub cursors: Vec<Cursor>,
     pub width: usize,
     pub height: usize,
     pub top_line_index: isize,
@@ -144,14 +430,61 @@ pub struct BufferView {
 }
 
 impl BufferView {
-    pub fn new(width: usize, height: usize, content: &str) -> Self {
+    pub fn new(buffer_index: usize, revision: u64, width: usize, height: usize, content: &str) -> Self {
+        let id = Uuid::new_v4().to_hyphenated_string();
         BufferView {
-            id: Uuid::new_v4().to_hyphenated_string(),
+            cursors: vec![Cursor {
+                id: primary_cursor_id(&id),
+                .. Cursor::new(Position { line_index: 0, column_index: 0 })
+            }],
+            id: id,
+            buffer_index: buffer_index,
+            revision: revision,
             top_line_index: 0,
             width: width,
             height: height,
             lines: content.split("\n").map(|s| s.into()).collect(),
-            cursor: Cursor::new(),
+        }
+    }
+
+    pub fn cursor(&self, id: &str) -> Option<&Cursor> {
+        self.cursors.iter().find(|cursor| cursor.id == id)
+    }
+
+    // Returns the closest position that exists in the buffer.
+    fn clamp(&self, position: Position) -> Position {
+        let line_index = cmp::max(0, cmp::min(self.lines.len() as isize - 1, position.line_index));
+        let column_index = match self.lines.get(line_index as usize) {
+            None => 0,
+            Some(line) => {
+                cmp::max(0, cmp::min(line.chars().count() as isize, position.column_index))
+            }
+        };
+        Position { line_index: line_index, column_index: column_index }
+    }
+
+    fn scroll_to(&mut self, position: Position) {
+        const SCROLLOFF: isize = 3;
+        let top_diff = position.line_index as isize - self.top_line_index;
+        if top_diff < SCROLLOFF {
+            self.scroll(top_diff - SCROLLOFF);
+        }
+        let bottom_diff = self.top_line_index + self.height as isize - position.line_index;
+        if bottom_diff < SCROLLOFF {
+            self.scroll(SCROLLOFF - bottom_diff);
+        }
+    }
+
+    // The position of the character before 'position' or None at the start of the buffer.
+    fn previous_position(&self, position: Position) -> Option<Position> {
+        if position.column_index > 0 {
+            Some(Position { line_index: position.line_index, column_index: position.column_index - 1 })
+        } else if position.line_index > 0 {
+            let line_index = position.line_index - 1;
+            let column_index = self.lines[line_index as usize].chars().count() as isize;
+            Some(Position { line_index: line_index, column_index: column_index })
+        } else {
+            None
         }
     }
 
@@ -164,6 +497,117 @@ impl BufferView {
     pub fn id(&self) -> &str {
         &self.id
     }
+
+    // Applies 'change' to our copy of the buffer. Returns false if the change does not fit our
+    // copy, because we missed a change or it is out of range. The content needs to be reloaded
+    // then.
+    fn apply_change(&mut self, change: &plugin::buffer::BufferChanged) -> bool {
+        if change.revision <= self.revision {
+            // We reloaded the content after this change was made.
+            return true;
+        }
+        if change.revision != self.revision + 1 {
+            return false;
+        }
+
+        // NOCOM(#sirver): this rebuilds all lines on every change. Only touch the changed ones.
+        let mut content = self.lines.join("\n");
+        let range = change.start.byte_index..change.end.byte_index;
+        if range.start > range.end || range.end > content.len() ||
+            !content.is_char_boundary(range.start) || !content.is_char_boundary(range.end) {
+            return false;
+        }
+        content.replace_range(range, &change.text);
+        self.lines = content.split("\n").map(|s| s.into()).collect();
+        self.revision = change.revision;
+
+        for cursor in &mut self.cursors {
+            cursor.position = cursor.position.adjusted(change);
+            cursor.anchor = cursor.anchor.adjusted(change);
+            cursor.wanted_position = cursor.position;
+        }
+        true
+    }
+
+    // Replaces our copy of the buffer with 'content' unless we are already further along. The
+    // cursors stay where they are as far as the new content allows.
+    fn reload(&mut self, content: &str, revision: u64) {
+        if revision <= self.revision {
+            return;
+        }
+        self.lines = content.split("\n").map(|s| s.into()).collect();
+        self.revision = revision;
+
+        for index in 0..self.cursors.len() {
+            let position = self.clamp(self.cursors[index].position);
+            let anchor = self.clamp(self.cursors[index].anchor);
+            let cursor = &mut self.cursors[index];
+            cursor.position = position;
+            cursor.anchor = anchor;
+            cursor.wanted_position = position;
+        }
+        self.scroll(0);
+    }
+}
+
+// Sorts 'ranges' and merges the ones that overlap or touch.
+fn merge_ranges(mut ranges: Vec<(Position, Position)>) -> Vec<(Position, Position)> {
+    ranges.sort();
+    let mut merged: Vec<(Position, Position)> = Vec::new();
+    for (start, end) in ranges {
+        if let Some(last) = merged.last_mut() {
+            if start <= last.1 {
+                last.1 = cmp::max(last.1, end);
+                continue;
+            }
+        }
+        merged.push((start, end));
+    }
+    merged
+}
+
+// Replaces each range with 'text' as one undo step. Ranges are applied from the end of the
+// buffer to the start, so that they do not shift each other. Overlapping ranges are merged.
+fn replace_ranges(client: &mut client::ThinClient, buffer_index: usize, ranges: Vec<(Position, Position)>, text: &str) -> Result<(), BufferViewError> {
+    try!(call_buffer_rpc(client, "buffer.begin_transaction", &plugin::buffer::begin_transaction::Request {
+        buffer_index: buffer_index,
+        timeout_ms: None,
+    }));
+    let mut result = Ok(());
+    for &(start, end) in merge_ranges(ranges).iter().rev() {
+        result = call_buffer_rpc(client, "buffer.replace", &plugin::buffer::replace::Request {
+            buffer_index: buffer_index,
+            start: start.to_buffer_position(),
+            end: end.to_buffer_position(),
+            text: text.into(),
+        });
+        if result.is_err() {
+            break;
+        }
+    }
+    try!(call_buffer_rpc(client, "buffer.end_transaction", &plugin::buffer::end_transaction::Request {
+        buffer_index: buffer_index,
+    }));
+    result
+}
+
+fn get_content(client: &mut client::ThinClient, buffer_index: usize) -> Result<plugin::buffer::get_content::Response, BufferViewError> {
+    let mut rpc = try!(client.call("buffer.get_content", &plugin::buffer::get_content::Request {
+        buffer_index: buffer_index,
+    }));
+    Ok(try!(rpc.wait_for()))
+}
+
+fn call_buffer_rpc<T: Serialize>(client: &mut client::ThinClient, function: &str, args: &T) -> Result<(), BufferViewError> {
+    let mut rpc = try!(client.call(function, args));
+    match try!(rpc.wait()) {
+        rpc::Result::Ok(_) => Ok(()),
+        rpc::Result::Err(err) => Err(BufferViewError::Rpc(err)),
+        rpc::Result::NotHandled => Err(BufferViewError::Rpc(rpc::Error {
+            kind: rpc::ErrorKind::UnknownRpc,
+            details: None,
+        })),
+    }
 }
 
 /// Handles all buffer views and dispatches RPC calls to them.
@@ -195,58 +639,197 @@ impl BufferViews {
         };
         try!(client.new_rpc("gui.buffer_view.move_cursor", Box::new(move_cursor)));
 
+        let set_cursor = SetCursor {
+            buffer_views: buffer_view.clone(),
+        };
+        try!(client.new_rpc("gui.buffer_view.set_cursor", Box::new(set_cursor)));
+
+        let add_cursor = AddCursor {
+            buffer_views: buffer_view.clone(),
+        };
+        try!(client.new_rpc("gui.buffer_view.add_cursor", Box::new(add_cursor)));
+
+        let remove_cursor = RemoveCursor {
+            buffer_views: buffer_view.clone(),
+        };
+        try!(client.new_rpc("gui.buffer_view.remove_cursor", Box::new(remove_cursor)));
+
+        let clear_cursors = ClearCursors {
+            buffer_views: buffer_view.clone(),
+        };
+        try!(client.new_rpc("gui.buffer_view.clear_cursors", Box::new(clear_cursors)));
+
+        let insert = Insert {
+            buffer_views: buffer_view.clone(),
+        };
+        try!(client.new_rpc("gui.buffer_view.insert", Box::new(insert)));
+
+        let delete = Delete {
+            buffer_views: buffer_view.clone(),
+        };
+        try!(client.new_rpc("gui.buffer_view.delete", Box::new(delete)));
+
+        let on_buffer_changed = OnBufferChanged {
+            buffer_views: buffer_view.clone(),
+        };
+        try!(client.new_rpc("on.buffer.changed", Box::new(on_buffer_changed)));
+
+        let on_buffer_reloaded = OnBufferReloaded {
+            buffer_views: buffer_view.clone(),
+        };
+        try!(client.new_rpc("on.buffer.reloaded", Box::new(on_buffer_reloaded)));
+
         Ok(buffer_view)
     }
 
+    // Returns the view containing the cursor 'id' and the index of the cursor in it.
+    fn find_cursor(&mut self, id: &str) -> Result<(&mut BufferView, usize), BufferViewError> {
+        for buffer_view in self.buffer_views.values_mut() {
+            if let Some(index) = buffer_view.cursors.iter().position(|cursor| cursor.id == id) {
+                return Ok((buffer_view, index));
+            }
+        }
+        Err(BufferViewError::UnknownCursor)
+    }
+
+    fn get_mut(&mut self, id: &str) -> Result<&mut BufferView, BufferViewError> {
+        self.buffer_views.get_mut(id).ok_or(BufferViewError::UnknownBufferView)
+    }
+
+    fn redraw(&self) {
+        self.commands.lock().unwrap().send(GuiCommand::Redraw).unwrap();
+    }
+
     // NOCOM(#sirver): write tests for move cursor.
-    fn move_cursor(&mut self, id: &str, delta: Position) -> Result<(), BufferViewError> {
-        for (_, buffer_view) in self.buffer_views.iter_mut() {
-            if buffer_view.cursor.id == id {
-                buffer_view.cursor.wanted_position = buffer_view.cursor.wanted_position + delta;
-                let mut new_pos = buffer_view.cursor.wanted_position;
-
-                new_pos.line_index = cmp::max(0, cmp::min(buffer_view.lines.len() as isize - 1, new_pos.line_index));
-                new_pos.column_index = match buffer_view.lines.get(new_pos.line_index as usize) {
-                    None => 0,
-                    Some(line) => {
-                        cmp::max(0, cmp::min(line.len() as isize - 1, new_pos.column_index))
-                    }
-                };
-
-                buffer_view.cursor.position = new_pos;
-                const SCROLLOFF: isize = 3;
-                let top_diff = new_pos.line_index as isize - buffer_view.top_line_index;
-                if top_diff < SCROLLOFF {
-                    buffer_view.scroll(top_diff - SCROLLOFF);
-                }
-                let bottom_diff = buffer_view.top_line_index + buffer_view.height as isize - new_pos.line_index;
-                if bottom_diff < SCROLLOFF {
-                    buffer_view.scroll(SCROLLOFF - bottom_diff);
+    fn move_cursor(&mut self, id: &str, delta: Position, extend_selection: bool) -> Result<(), BufferViewError> {
+        {
+            let (buffer_view, index) = try!(self.find_cursor(id));
+            let wanted_position = buffer_view.cursors[index].wanted_position + delta;
+            let position = buffer_view.clamp(wanted_position);
+            {
+                let cursor = &mut buffer_view.cursors[index];
+                cursor.wanted_position = wanted_position;
+                cursor.position = position;
+                if !extend_selection {
+                    cursor.anchor = position;
                 }
+            }
+            buffer_view.scroll_to(position);
+        }
+
+        // TODO(sirver): Publish the cursor position for other GUIs.
+        self.redraw();
+        Ok(())
+    }
 
-                // TODO(sirver): Publish the cursor position for other GUIs.
+    fn set_cursor(&mut self, id: &str, position: Position, extend_selection: bool) -> Result<(), BufferViewError> {
+        {
+            let (buffer_view, index) = try!(self.find_cursor(id));
+            let position = buffer_view.clamp(position);
+            {
+                let cursor = &mut buffer_view.cursors[index];
+                cursor.wanted_position = position;
+                cursor.position = position;
+                if !extend_selection {
+                    cursor.anchor = position;
+                }
+            }
+            buffer_view.scroll_to(position);
+        }
+        self.redraw();
+        Ok(())
+    }
 
-                self.commands.lock().unwrap().send(GuiCommand::Redraw).unwrap();
+    fn add_cursor(&mut self, buffer_view_id: &str, position: Position) -> Result<String, BufferViewError> {
+        let cursor_id = {
+            let buffer_view = try!(self.get_mut(buffer_view_id));
+            let cursor = Cursor::new(buffer_view.clamp(position));
+            let cursor_id = cursor.id().to_string();
+            buffer_view.cursors.push(cursor);
+            cursor_id
+        };
+        self.redraw();
+        Ok(cursor_id)
+    }
 
-                return Ok(());
+    fn remove_cursor(&mut self, id: &str) -> Result<(), BufferViewError> {
+        {
+            let (buffer_view, index) = try!(self.find_cursor(id));
+            if index == 0 {
+                return Err(BufferViewError::PrimaryCursor);
             }
+            buffer_view.cursors.remove(index);
         }
-        Err(BufferViewError::UnknownCursor)
+        self.redraw();
+        Ok(())
     }
 
-    pub fn new_view(&mut self, buffer_index: usize, width: usize, height: usize) -> String {
-        let mut rpc = self.client.call("buffer.get_content", &plugin::buffer::get_content::Request {
-            buffer_index: buffer_index,
-        }).unwrap();
+    fn clear_cursors(&mut self, buffer_view_id: &str) -> Result<(), BufferViewError> {
+        {
+            let buffer_view = try!(self.get_mut(buffer_view_id));
+            buffer_view.cursors.truncate(1);
+            let cursor = &mut buffer_view.cursors[0];
+            cursor.anchor = cursor.position;
+        }
+        self.redraw();
+        Ok(())
+    }
+
+    // Returns the buffer of the view and the ranges that inserting replaces. The cursors are moved
+    // once the buffer tells us about the changes.
+    fn insert_ranges(&self, buffer_view_id: &str) -> Result<(usize, Vec<(Position, Position)>), BufferViewError> {
+        let buffer_view = try!(self.buffer_views.get(buffer_view_id).ok_or(BufferViewError::UnknownBufferView));
+        let ranges = buffer_view.cursors.iter().map(|cursor| cursor.selection()).collect();
+        Ok((buffer_view.buffer_index, ranges))
+    }
 
-        let response: plugin::buffer::get_content::Response = rpc.wait_for().unwrap();
-        let buffer_view = BufferView::new(width, height, &response.content);
+    // Like 'insert_ranges', but for deleting.
+    fn delete_ranges(&self, buffer_view_id: &str) -> Result<(usize, Vec<(Position, Position)>), BufferViewError> {
+        let buffer_view = try!(self.buffer_views.get(buffer_view_id).ok_or(BufferViewError::UnknownBufferView));
+        let ranges = buffer_view.cursors.iter().filter_map(|cursor| {
+            if cursor.has_selection() {
+                Some(cursor.selection())
+            } else {
+                buffer_view.previous_position(cursor.position).map(|start| (start, cursor.position))
+            }
+        }).collect();
+        Ok((buffer_view.buffer_index, ranges))
+    }
+
+    pub fn new_view(&mut self, buffer_index: usize, width: usize, height: usize) -> String {
+        let response = get_content(&mut self.client, buffer_index).unwrap();
+        let buffer_view = BufferView::new(buffer_index, response.revision, width, height, &response.content);
         let view_id = buffer_view.id().to_string();
         self.buffer_views.insert(buffer_view.id().to_string(), buffer_view);
         view_id
     }
 
 
+    // Returns true if a view of the buffer could not apply 'change' and needs to be reloaded.
+    fn on_buffer_changed(&mut self, change: &plugin::buffer::BufferChanged) -> bool {
+        let mut changed = false;
+        let mut needs_reload = false;
+        for buffer_view in self.buffer_views.values_mut() {
+            if buffer_view.buffer_index == change.buffer_index {
+                needs_reload |= !buffer_view.apply_change(change);
+                changed = true;
+            }
+        }
+        if changed {
+            self.redraw();
+        }
+        needs_reload
+    }
+
+    fn reload(&mut self, buffer_index: usize, content: &str, revision: u64) {
+        for buffer_view in self.buffer_views.values_mut() {
+            if buffer_view.buffer_index == buffer_index {
+                buffer_view.reload(content, revision);
+            }
+        }
+        self.redraw();
+    }
+
     pub fn get(&self, id: &str) -> Option<&BufferView> {
         self.buffer_views.get(id)
     }
@@ -261,3 +844,139 @@ impl BufferViews {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swiboe::plugin;
+
+    fn position(line_index: isize, column_index: isize) -> Position {
+        Position { line_index: line_index, column_index: column_index }
+    }
+
+    fn location(content: &str, byte_index: usize) -> plugin::buffer::Location {
+        let before = &content[..byte_index];
+        let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+        plugin::buffer::Location {
+            byte_index: byte_index,
+            char_index: before.chars().count(),
+            line_index: before.matches('\n').count(),
+            column_index: before[line_start..].chars().count(),
+        }
+    }
+
+    // The change replacing the bytes from 'start' to 'end' of 'content' with 'text'.
+    fn change(content: &str, revision: u64, start: usize, end: usize, text: &str) -> plugin::buffer::BufferChanged {
+        plugin::buffer::BufferChanged {
+            buffer_index: 0,
+            revision: revision,
+            start: location(content, start),
+            end: location(content, end),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn merge_overlapping_and_touching_ranges() {
+        let merged = merge_ranges(vec![
+            (position(2, 0), position(2, 3)),
+            (position(0, 1), position(0, 4)),
+            (position(0, 3), position(1, 0)),
+            (position(1, 0), position(1, 0)),
+            (position(3, 0), position(3, 1)),
+        ]);
+        assert_eq!(vec![
+            (position(0, 1), position(1, 0)),
+            (position(2, 0), position(2, 3)),
+            (position(3, 0), position(3, 1)),
+        ], merged);
+    }
+
+    #[test]
+    fn selection_is_in_document_order() {
+        let mut cursor = Cursor::new(position(1, 2));
+        assert!(!cursor.has_selection());
+        assert_eq!((position(1, 2), position(1, 2)), cursor.selection());
+
+        cursor.position = position(0, 5);
+        assert!(cursor.has_selection());
+        assert_eq!((position(0, 5), position(1, 2)), cursor.selection());
+    }
+
+    #[test]
+    fn every_view_has_its_own_primary_cursor() {
+        let first = BufferView::new(0, 0, 80, 25, "");
+        let second = BufferView::new(0, 0, 80, 25, "");
+        assert_eq!(primary_cursor_id(first.id()), first.cursors[0].id());
+        assert_eq!(primary_cursor_id(second.id()), second.cursors[0].id());
+        assert!(first.cursors[0].id() != second.cursors[0].id());
+    }
+
+    #[test]
+    fn apply_change_moves_cursors() {
+        let content = "one\ntwo\nthree";
+        let mut buffer_view = BufferView::new(0, 0, 80, 25, content);
+        buffer_view.cursors[0].position = position(1, 1);
+        buffer_view.cursors.push(Cursor::new(position(2, 2)));
+        buffer_view.cursors.push(Cursor::new(position(0, 1)));
+
+        assert!(buffer_view.apply_change(&change(content, 1, 4, 4, "a\nb")));
+        assert_eq!(vec!["one", "a", "btwo", "three"], buffer_view.lines);
+        assert_eq!(1, buffer_view.revision);
+        assert_eq!(position(2, 2), buffer_view.cursors[0].position);
+        assert_eq!(position(3, 2), buffer_view.cursors[1].position);
+        assert_eq!(position(0, 1), buffer_view.cursors[2].position);
+    }
+
+    #[test]
+    fn apply_change_ignores_changes_we_already_have() {
+        let content = "one\ntwo";
+        let mut buffer_view = BufferView::new(0, 3, 80, 25, content);
+        assert!(buffer_view.apply_change(&change(content, 3, 0, 3, "")));
+        assert_eq!(vec!["one", "two"], buffer_view.lines);
+        assert_eq!(3, buffer_view.revision);
+    }
+
+    #[test]
+    fn apply_change_needs_reload_after_missed_change() {
+        let content = "one\ntwo";
+        let mut buffer_view = BufferView::new(0, 0, 80, 25, content);
+        assert!(!buffer_view.apply_change(&change(content, 2, 0, 3, "")));
+        assert_eq!(vec!["one", "two"], buffer_view.lines);
+        assert_eq!(0, buffer_view.revision);
+    }
+
+    #[test]
+    fn apply_change_needs_reload_for_invalid_ranges() {
+        let content = "bläh";
+        let mut buffer_view = BufferView::new(0, 0, 80, 25, content);
+
+        let mut out_of_range = change(content, 1, 0, 5, "");
+        out_of_range.end.byte_index = 10;
+        assert!(!buffer_view.apply_change(&out_of_range));
+
+        let mut inside_of_char = change(content, 1, 0, 2, "");
+        inside_of_char.end.byte_index = 3;
+        assert!(!buffer_view.apply_change(&inside_of_char));
+
+        assert_eq!(vec!["bläh"], buffer_view.lines);
+        assert_eq!(0, buffer_view.revision);
+    }
+
+    #[test]
+    fn reload_clamps_cursors() {
+        let mut buffer_view = BufferView::new(0, 0, 80, 25, "one\ntwo\nthree");
+        buffer_view.cursors[0].position = position(2, 4);
+        buffer_view.cursors[0].anchor = position(1, 1);
+
+        buffer_view.reload("a\nbc", 5);
+        assert_eq!(vec!["a", "bc"], buffer_view.lines);
+        assert_eq!(5, buffer_view.revision);
+        assert_eq!(position(1, 2), buffer_view.cursors[0].position);
+        assert_eq!(position(1, 1), buffer_view.cursors[0].anchor);
+
+        // Older content is ignored.
+        buffer_view.reload("old", 4);
+        assert_eq!(vec!["a", "bc"], buffer_view.lines);
+    }
+}
@@ -147,6 +147,14 @@ impl ConfigFileRunner {
         this
     }
 
+    /// Makes 'cursor_id' available to mappings as 'swiboe.current_cursor_id'.
+    pub fn set_current_cursor_id(&mut self, cursor_id: &str) {
+        self.lua_state.get_global("swiboe");
+        self.lua_state.push_string(cursor_id);
+        self.lua_state.set_field(-2, "current_cursor_id");
+        self.lua_state.pop(1);
+    }
+
     pub fn run(&mut self, path: &path::Path) {
         let path = path.to_string_lossy();
         match self.lua_state.do_file(&path) {
@@ -149,12 +149,18 @@ impl KeymapHandler {
         self.keymaps.push(mapping);
     }
 
-    pub fn key_down(&mut self, delta_t: f64, key: Key) {
+    /// Returns true if the key completed a mapping, which was run then.
+    pub fn key_down(&mut self, delta_t: f64, key: Key) -> bool {
         self.current_key_events.push(KeyEvent {
             delta_t: delta_t,
             key: key,
         });
-        self.check_if_current_key_match();
+        self.check_if_current_key_match()
+    }
+
+    /// Forgets the keys pressed so far, e.g. because the GUI used them for something else.
+    pub fn clear(&mut self) {
+        self.current_key_events.clear();
     }
 
     // NOCOM(#sirver): this should be triggered after a while and the
@@ -164,7 +170,7 @@ impl KeymapHandler {
         self.current_key_events.clear();
     }
 
-    pub fn check_if_current_key_match(&mut self) {
+    pub fn check_if_current_key_match(&mut self) -> bool {
         let mut arpeggio: Arpeggio = Arpeggio::new();
         for key_event in &self.current_key_events {
             // NOCOM(#sirver): make configurable
@@ -186,7 +192,9 @@ impl KeymapHandler {
             let mut mapping = possible_keys.last_mut().unwrap();
             (mapping.function)();
             self.current_key_events.clear();
+            return true;
         }
+        false
     }
 }
 
@@ -244,8 +252,26 @@ mod tests {
                 v_clone.set(true);
             })
         ));
-        keymap_handler.key_down(1000., Key::Down);
-        keymap_handler.key_down(20e-3, Key::Up);
+        assert!(!keymap_handler.key_down(1000., Key::Down));
+        assert!(keymap_handler.key_down(20e-3, Key::Up));
+
+        assert_eq!(v.get(), true);
+    }
+
+    #[test]
+    fn test_clear_forgets_pressed_keys() {
+        let mut keymap_handler = KeymapHandler::new();
+
+        let v = rc::Rc::new(cell::Cell::new(false));
+        let v_clone = v.clone();
+        keymap_handler.insert(Mapping::new(
+            Arpeggio::new().append(Chord::with(Key::Char('i'))), Box::new(move || {
+                v_clone.set(true);
+            })
+        ));
+        assert!(!keymap_handler.key_down(1000., Key::Char('x')));
+        keymap_handler.clear();
+        assert!(keymap_handler.key_down(1000., Key::Char('i')));
 
         assert_eq!(v.get(), true);
     }
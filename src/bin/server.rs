@@ -32,6 +32,35 @@ fn main() {
                 )
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("AUTH_TOKEN_FILE")
+                .long("auth_token_file")
+                .help(
+                    "File containing a secret token that clients connecting over TCP need to \
+                   present.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("TLS_CERTIFICATE")
+                .long("tls_certificate")
+                .help("PEM file with the certificate chain to use for TLS on TCP connections.")
+                .requires("TLS_PRIVATE_KEY")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("TLS_PRIVATE_KEY")
+                .long("tls_private_key")
+                .help("PEM file with the private key for --tls_certificate.")
+                .requires("TLS_CERTIFICATE")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("MAX_FRAME_SIZE")
+                .long("max_frame_size")
+                .help("Clients sending messages bigger than this many bytes are disconnected.")
+                .takes_value(true),
+        )
         .get_matches();
 
     let path = Path::new(matches.value_of("SOCKET").unwrap());
@@ -41,6 +70,26 @@ fn main() {
         Vec::new()
     };
 
-    let mut server = swiboe::server::Server::launch(path, &ips).unwrap();
+    let mut config = swiboe::server::Config::default();
+    if let Some(auth_token_file) = matches.value_of("AUTH_TOKEN_FILE") {
+        config.auth_token = Some(
+            swiboe::ipc::read_auth_token(Path::new(auth_token_file))
+                .expect("Could not read --auth_token_file."),
+        );
+    }
+    if let Some(certificate_file) = matches.value_of("TLS_CERTIFICATE") {
+        let private_key_file = matches.value_of("TLS_PRIVATE_KEY").unwrap();
+        config.tls = Some(
+            swiboe::tls::server_config(Path::new(certificate_file), Path::new(private_key_file))
+                .expect("Could not load TLS certificate or private key."),
+        );
+    }
+    if let Some(max_frame_size) = matches.value_of("MAX_FRAME_SIZE") {
+        config.max_frame_size = max_frame_size
+            .parse()
+            .expect("--max_frame_size needs to be a number.");
+    }
+
+    let mut server = swiboe::server::Server::launch_with_config(path, &ips, config).unwrap();
     server.wait_for_shutdown();
 }
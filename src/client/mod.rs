@@ -4,19 +4,24 @@
 
 #![allow(deprecated)]
 
-use error::Result;
+use error::{Error, Result};
 use ipc;
 
 // NOCOM such class/module should be pulled out
 //       server and client should not depend each other
-use server::plugin_core::NewRpcRequest;
+use server::plugin_core::{DeleteRpcRequest, NewRpcRequest};
 
+use rustls;
 use serde;
+use std::env;
 use std::io;
 use std::net::{self, TcpStream};
 use std::path;
-use std::sync::{mpsc, Mutex};
+use std::process;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time;
+use tls;
 use unix_socket::UnixStream;
 
 /// An abstraction that can call remove RPCs.
@@ -26,6 +31,52 @@ pub trait RpcCaller {
         function: &str,
         args: &T,
     ) -> Result<::client::rpc::client::Context>;
+
+    /// Like 'call', but the RPC fails with 'ErrorKind::Timeout' if it does not finish in time.
+    fn call_with_timeout<T: serde::Serialize>(
+        &mut self,
+        function: &str,
+        args: &T,
+        timeout: time::Duration,
+    ) -> Result<::client::rpc::client::Context>;
+
+    /// Calls 'function' on every client that registered it, not only the first one that handles
+    /// it. There is no way to know when or how the handlers finished.
+    fn broadcast<T: serde::Serialize>(&mut self, function: &str, args: &T) -> Result<()>;
+}
+
+// Introduces us to the server and agrees on the encoding for the connection. We prefer the compact
+// one.
+fn handshake<R: io::Read, W: io::Write>(
+    reader: &mut ipc::Reader<R>,
+    writer: &mut ipc::Writer<W>,
+    auth_token: Option<&str>,
+) -> Result<ipc::Welcome> {
+    // We name ourselves after the program we are running in.
+    let name = env::current_exe()
+        .ok()
+        .and_then(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "unknown".into());
+    writer.write_message(&ipc::Message::Hello(ipc::Hello {
+        version: ipc::PROTOCOL_VERSION,
+        name: name,
+        pid: process::id(),
+        features: ipc::features(),
+        encodings: vec![ipc::Encoding::MessagePack, ipc::Encoding::Json],
+        auth_token: auth_token.map(|token| token.to_string()),
+    }))?;
+    match reader.read_message()? {
+        ipc::Message::Welcome(welcome) => Ok(welcome),
+        ipc::Message::Refused(refused) => Err(Error::Refused(refused.reason)),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected Welcome, got {:?}", other),
+        )
+        .into()),
+    }
 }
 
 /// A client maintains a connection to a Swiboe server. It can also serve RPCs that can only be
@@ -42,90 +93,211 @@ pub struct Client {
     read_thread: Option<thread::JoinHandle<()>>,
     write_thread: Option<thread::JoinHandle<()>>,
 
-    // Function to bring down the connection used for IO. The 'read_thread' and 'write_thread' will
-    // both error then and terminate.
-    shutdown_socket_func: Box<dyn Fn() -> ()>,
+    // Function to bring down the connection used for IO. The 'read_thread' will error then and
+    // terminate. The 'read_thread' also uses it to drop the connection if reading fails.
+    shutdown_socket_func: Arc<dyn Fn(net::Shutdown) + Send + Sync>,
+
+    // What the server told us it supports.
+    server_features: Vec<String>,
 }
 
 impl Client {
     pub fn connect_unix(socket_name: &path::Path) -> Result<Self> {
+        Client::connect_unix_with_max_frame_size(socket_name, ipc::DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// Like 'connect_unix', but accepts messages of up to 'max_frame_size' bytes from the server.
+    /// This should match the 'max_frame_size' of the server, otherwise answers to big calls drop
+    /// the connection.
+    pub fn connect_unix_with_max_frame_size(
+        socket_name: &path::Path,
+        max_frame_size: usize,
+    ) -> Result<Self> {
+        Client::unix_connect(socket_name, None, max_frame_size)
+    }
+
+    /// Like 'connect_unix', but presents 'plugin_token' and accepts messages of any size. The
+    /// server runs its own plugins like this.
+    #[doc(hidden)]
+    pub fn connect_unix_as_plugin(socket_name: &path::Path, plugin_token: &str) -> Result<Self> {
+        Client::unix_connect(socket_name, Some(plugin_token), usize::MAX)
+    }
+
+    fn unix_connect(
+        socket_name: &path::Path,
+        auth_token: Option<&str>,
+        max_frame_size: usize,
+    ) -> Result<Self> {
         let writer_stream = UnixStream::connect(&socket_name)?;
         let reader_stream = writer_stream.try_clone()?;
         let shutdown_stream = writer_stream.try_clone()?;
-        Ok(Client::common_connect(
+        Client::common_connect(
             reader_stream,
             writer_stream,
-            Box::new(move || {
-                let _ = shutdown_stream.shutdown(net::Shutdown::Read);
+            Arc::new(move |how| {
+                let _ = shutdown_stream.shutdown(how);
             }),
-        ))
+            auth_token,
+            max_frame_size,
+        )
     }
 
     pub fn connect_tcp(address: &net::SocketAddr) -> Result<Self> {
+        Client::tcp_connect(address, None)
+    }
+
+    /// Connects to a server that wants 'auth_token' from clients connecting over TCP.
+    pub fn connect_tcp_with_auth_token(
+        address: &net::SocketAddr,
+        auth_token: &str,
+    ) -> Result<Self> {
+        Client::tcp_connect(address, Some(auth_token))
+    }
+
+    /// Connects to a server that talks TLS. Its certificate needs to be issued for 'server_name'
+    /// and be trusted by 'tls_config', see 'tls::client_config'.
+    pub fn connect_tls(
+        address: &net::SocketAddr,
+        server_name: &str,
+        tls_config: Arc<rustls::ClientConfig>,
+        auth_token: Option<&str>,
+    ) -> Result<Self> {
+        let session = tls::client_session(tls_config, server_name)?;
+        let writer_socket = TcpStream::connect(address)?;
+        let reader_socket = writer_socket.try_clone()?;
+        let shutdown_socket = writer_socket.try_clone()?;
+        let writer_stream = tls::TlsStream::new(writer_socket, session);
+        let reader_stream = writer_stream.share(reader_socket);
+        Client::common_connect(
+            reader_stream,
+            writer_stream,
+            Arc::new(move |how| {
+                let _ = shutdown_socket.shutdown(how);
+            }),
+            auth_token,
+            ipc::DEFAULT_MAX_FRAME_SIZE,
+        )
+    }
+
+    fn tcp_connect(address: &net::SocketAddr, auth_token: Option<&str>) -> Result<Self> {
         let writer_stream = TcpStream::connect(address)?;
         let reader_stream = writer_stream.try_clone()?;
         let shutdown_stream = writer_stream.try_clone()?;
-        Ok(Client::common_connect(
+        Client::common_connect(
             reader_stream,
             writer_stream,
-            Box::new(move || {
-                let _ = shutdown_stream.shutdown(net::Shutdown::Read);
+            Arc::new(move |how| {
+                let _ = shutdown_stream.shutdown(how);
             }),
-        ))
+            auth_token,
+            ipc::DEFAULT_MAX_FRAME_SIZE,
+        )
     }
 
     fn common_connect<Reader: io::Read + Send + 'static, Writer: io::Write + Send + 'static>(
         reader_stream: Reader,
         writer_stream: Writer,
-        shutdown_func: Box<dyn Fn() -> ()>,
-    ) -> Self {
+        shutdown_func: Arc<dyn Fn(net::Shutdown) + Send + Sync>,
+        auth_token: Option<&str>,
+        max_frame_size: usize,
+    ) -> Result<Self> {
+        let mut reader = ipc::Reader::new(reader_stream);
+        reader.set_max_frame_size(max_frame_size);
+        let mut writer = ipc::Writer::new(writer_stream);
+        let welcome = handshake(&mut reader, &mut writer, auth_token)?;
+        reader.set_encoding(welcome.encoding);
+        writer.set_encoding(welcome.encoding);
+
         let (commands_tx, commands_rx) = mpsc::channel();
         let (send_tx, send_rx) = mpsc::channel::<ipc::Message>();
 
         let reader_commands_tx = commands_tx.clone();
+        let reader_shutdown_func = shutdown_func.clone();
         let read_thread = thread::spawn(move || {
-            let mut reader = ipc::Reader::new(reader_stream);
-            while let Ok(message) = reader.read_message() {
-                let command = rpc_loop::Command::Received(message);
-                if reader_commands_tx.send(command).is_err() {
-                    break;
+            let err = loop {
+                match reader.read_message() {
+                    Ok(message) => {
+                        let command = rpc_loop::Command::Received(message);
+                        if reader_commands_tx.send(command).is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => break err,
                 }
-            }
+            };
+            // The server is gone or sent something we cannot read. We cannot find the start of
+            // the next message then, so the connection is of no use anymore.
+            reader_shutdown_func(net::Shutdown::Both);
+            let _ = reader_commands_tx.send(rpc_loop::Command::Disconnected(err));
         });
 
         let write_thread = thread::spawn(move || {
-            let mut writer = ipc::Writer::new(writer_stream);
             while let Ok(message) = send_rx.recv() {
-                writer.write_message(&message).expect("Writing failed");
+                if writer.write_message(&message).is_err() {
+                    break;
+                }
             }
         });
 
-        Client {
+        Ok(Client {
             rpc_loop_commands: commands_tx.clone(),
             rpc_loop_thread: Some(rpc_loop::spawn(commands_rx, commands_tx, send_tx)),
             read_thread: Some(read_thread),
             write_thread: Some(write_thread),
             shutdown_socket_func: shutdown_func,
-        }
+            server_features: welcome.features,
+        })
+    }
+
+    /// The optional parts of the protocol the server supports.
+    pub fn server_features(&self) -> &[String] {
+        &self.server_features
     }
 
     pub fn new_rpc(&mut self, name: &str, rpc: Box<dyn rpc::server::Rpc>) -> Result<()> {
-        let mut new_rpc = self.call(
-            "core.new_rpc",
-            &NewRpcRequest {
-                priority: rpc.priority(),
-                name: name.into(),
-            }
-        )?;
-        let result = new_rpc.wait();
+        let request = NewRpcRequest {
+            priority: rpc.priority(),
+            name: name.into(),
+            schema: rpc.schema(),
+        };
 
-        if !result.is_ok() {
-            return Err(result.unwrap_err().into());
+        // The server routes calls to us as soon as it registered the function, which can be
+        // before we see its answer. So we need to know the function already.
+        let (inserted_tx, inserted_rx) = mpsc::channel();
+        self.rpc_loop_commands
+            .send(rpc_loop::Command::NewRpc(name.into(), rpc, inserted_tx))
+            .expect("NewRpc");
+        let inserted = inserted_rx.recv().expect("NewRpc");
+
+        let result = self
+            .call("core.new_rpc", &request)
+            .and_then(|mut new_rpc| new_rpc.wait());
+        let err = match result {
+            Ok(::rpc::Result::Err(err)) => err.into(),
+            Err(err) => err,
+            Ok(_) => return Ok(()),
+        };
+        // The server refused, so we must not keep a function we only added for this call.
+        if inserted {
+            self.rpc_loop_commands
+                .send(rpc_loop::Command::DeleteRpc(name.into()))
+                .expect("DeleteRpc");
+        }
+        Err(err)
+    }
+
+    /// Removes the RPC 'name' that was registered by this client through 'new_rpc'. Calls that
+    /// are already running are not affected.
+    pub fn delete_rpc(&mut self, name: &str) -> Result<()> {
+        let mut delete_rpc =
+            self.call("core.delete_rpc", &DeleteRpcRequest { name: name.into() })?;
+        if let ::rpc::Result::Err(err) = delete_rpc.wait()? {
+            return Err(err.into());
         }
 
         self.rpc_loop_commands
-            .send(rpc_loop::Command::NewRpc(name.into(), rpc))
-            .expect("NewRpc");
+            .send(rpc_loop::Command::DeleteRpc(name.into()))
+            .expect("DeleteRpc");
         Ok(())
     }
 
@@ -144,6 +316,24 @@ impl RpcCaller for Client {
     ) -> Result<rpc::client::Context> {
         rpc::client::Context::new(self.rpc_loop_commands.clone(), function, args)
     }
+
+    fn call_with_timeout<T: serde::Serialize>(
+        &mut self,
+        function: &str,
+        args: &T,
+        timeout: time::Duration,
+    ) -> Result<rpc::client::Context> {
+        rpc::client::Context::with_timeout(
+            self.rpc_loop_commands.clone(),
+            function,
+            args,
+            Some(timeout),
+        )
+    }
+
+    fn broadcast<T: serde::Serialize>(&mut self, function: &str, args: &T) -> Result<()> {
+        rpc::client::broadcast(&self.rpc_loop_commands, function, args)
+    }
 }
 
 impl Drop for Client {
@@ -153,11 +343,13 @@ impl Drop for Client {
             thread.join().expect("Joining rpc_loop_thread failed.");
         }
 
-        (self.shutdown_socket_func)();
-
+        // The rpc_loop is gone, so the 'write_thread' terminates once it wrote what is left.
         if let Some(thread) = self.write_thread.take() {
             thread.join().expect("Joining write_thread failed.");
         }
+
+        (self.shutdown_socket_func)(net::Shutdown::Read);
+
         if let Some(thread) = self.read_thread.take() {
             thread.join().expect("Joining read_thread failed.");
         }
@@ -194,6 +386,24 @@ impl RpcCaller for ThinClient {
         };
         rpc::client::Context::new(commands, function, args)
     }
+
+    fn call_with_timeout<T: serde::Serialize>(
+        &mut self,
+        function: &str,
+        args: &T,
+        timeout: time::Duration,
+    ) -> Result<rpc::client::Context> {
+        let commands = {
+            let commands = self.rpc_loop_commands.lock().unwrap();
+            commands.clone()
+        };
+        rpc::client::Context::with_timeout(commands, function, args, Some(timeout))
+    }
+
+    fn broadcast<T: serde::Serialize>(&mut self, function: &str, args: &T) -> Result<()> {
+        let commands = self.rpc_loop_commands.lock().unwrap();
+        rpc::client::broadcast(&commands, function, args)
+    }
 }
 
 mod rpc_loop;
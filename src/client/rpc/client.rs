@@ -6,13 +6,34 @@ use client::rpc_loop::{Command, CommandSender};
 use error::{Error, Result};
 use serde;
 use serde_json;
+use std::marker;
 use std::sync::mpsc;
+use std::time;
 use uuid::Uuid;
 
+/// Calls 'function' on all its handlers without waiting for any of them.
+pub fn broadcast<T: serde::Serialize>(
+    commands: &CommandSender,
+    function: &str,
+    args: &T,
+) -> Result<()> {
+    let message = ::ipc::Message::RpcBroadcast(::rpc::Broadcast {
+        function: function.into(),
+        context: Uuid::new_v4().to_hyphenated_string(),
+        args: serde_json::to_value(&args)?,
+    });
+    commands.send(Command::Send(message))?;
+    Ok(())
+}
+
+/// An RPC we called. Dropping it before the RPC finished cancels the RPC.
 pub struct Context {
     context: String,
     values: mpsc::Receiver<::rpc::Response>,
     result: Option<::rpc::Result>,
+    // True once the last response arrived or we cancelled. 'result' is taken out by 'wait', so it
+    // cannot tell.
+    finished: bool,
     commands: CommandSender,
 }
 
@@ -21,6 +42,28 @@ impl Context {
         commands: CommandSender,
         function: &str,
         args: &T,
+    ) -> Result<Self> {
+        Context::with_timeout(commands, function, args, None)
+    }
+
+    /// Like 'new', but the server cancels the RPC if it did not finish after 'timeout'.
+    pub fn with_timeout<T: serde::Serialize>(
+        commands: CommandSender,
+        function: &str,
+        args: &T,
+        timeout: Option<time::Duration>,
+    ) -> Result<Self> {
+        Context::with_parent(commands, function, args, timeout, None)
+    }
+
+    /// Like 'with_timeout', but the call is made on behalf of the RPC with the context 'parent'
+    /// and gets cancelled together with it.
+    pub fn with_parent<T: serde::Serialize>(
+        commands: CommandSender,
+        function: &str,
+        args: &T,
+        timeout: Option<time::Duration>,
+        parent: Option<String>,
     ) -> Result<Self> {
         let args = serde_json::to_value(&args);
         let context = Uuid::new_v4().to_hyphenated_string();
@@ -28,6 +71,10 @@ impl Context {
             function: function.into(),
             context: context.clone(),
             args: args?,
+            deadline_ms: timeout.map(|timeout| {
+                timeout.as_secs() * 1000 + timeout.subsec_nanos() as u64 / 1_000_000
+            }),
+            parent: parent,
         });
 
         let (tx, rx) = mpsc::channel();
@@ -36,12 +83,12 @@ impl Context {
         commands
             .send(Command::OutgoingCall(context.clone(), tx, message))
             .expect("Command::OutgoingCall");
-        // NOCOM(#sirver): implement drop so that we can cancel an RPC.
         Ok(Context {
             values: rx,
             commands: commands,
             context: context,
             result: None,
+            finished: false,
         })
     }
 
@@ -57,28 +104,40 @@ impl Context {
                 _ => return Err(Error::Disconnected),
             },
         };
-
-        match rpc_response.kind {
-            ::rpc::ResponseKind::Partial(value) => Ok(Some(value)),
-            ::rpc::ResponseKind::Last(result) => {
-                self.result = Some(result);
-                Ok(None)
-            }
-        }
+        Ok(self.handle_response(rpc_response))
     }
 
-    // NOCOM(#sirver): timeout?
     pub fn recv(&mut self) -> Result<Option<serde_json::Value>> {
         if self.result.is_some() {
             return Ok(None);
         }
 
         let rpc_response = self.values.recv()?;
+        Ok(self.handle_response(rpc_response))
+    }
+
+    /// Like 'recv', but gives up with 'Error::Timeout' if nothing arrived after 'timeout'. The
+    /// RPC keeps running.
+    pub fn recv_timeout(&mut self, timeout: time::Duration) -> Result<Option<serde_json::Value>> {
+        if self.result.is_some() {
+            return Ok(None);
+        }
+
+        let rpc_response = match self.values.recv_timeout(timeout) {
+            Ok(value) => value,
+            Err(mpsc::RecvTimeoutError::Timeout) => return Err(Error::Timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(Error::Disconnected),
+        };
+        Ok(self.handle_response(rpc_response))
+    }
+
+    fn handle_response(&mut self, rpc_response: ::rpc::Response) -> Option<serde_json::Value> {
         match rpc_response.kind {
-            ::rpc::ResponseKind::Partial(value) => Ok(Some(value)),
+            ::rpc::ResponseKind::Partial(value) => Some(value),
             ::rpc::ResponseKind::Last(result) => {
                 self.result = Some(result);
-                Ok(None)
+                self.finished = true;
+                None
             }
         }
     }
@@ -89,22 +148,88 @@ impl Context {
         Ok(self.result.take().unwrap())
     }
 
+    /// Like 'wait', but gives up with 'Error::Timeout' if the RPC did not finish after 'timeout'.
+    /// The RPC keeps running, so 'wait' can be called again later.
+    pub fn wait_timeout(&mut self, timeout: time::Duration) -> Result<::rpc::Result> {
+        let deadline = time::Instant::now() + timeout;
+        while self.result.is_none() {
+            let now = time::Instant::now();
+            if now >= deadline {
+                return Err(Error::Timeout);
+            }
+            self.recv_timeout(deadline - now)?;
+        }
+        Ok(self.result.take().unwrap())
+    }
+
     // NOCOM(#sirver): this feels not useful, once wait() consumes the context.
     pub fn done(&self) -> bool {
         self.result.is_some()
     }
 
+    /// Like 'wait', but deserializes a successful result into 'T'. A failed RPC is turned into
+    /// 'Error::Rpc', an unhandled one into 'Error::NotHandled'.
     pub fn wait_for<T: for<'a> serde::Deserialize<'a>>(&mut self) -> Result<T> {
         match self.wait()? {
             ::rpc::Result::Ok(value) => Ok(serde_json::from_value(value)?),
-            ::rpc::Result::Err(err) => panic!("#sirver err: {:#?}", err),
-            // NOCOM(#sirver): probably should ignore other errors.
-            other => panic!("#sirver other: {:#?}", other),
+            ::rpc::Result::Err(err) => Err(Error::Rpc(err)),
+            ::rpc::Result::NotHandled => Err(Error::NotHandled),
+        }
+    }
+
+    /// Iterates over the partial responses, deserialized into 'T'. The iteration ends once the
+    /// RPC finished, 'wait' or 'wait_for' then return its result.
+    pub fn updates<T: for<'a> serde::Deserialize<'a>>(&mut self) -> Updates<'_, T> {
+        Updates {
+            context: self,
+            failed: false,
+            phantom: marker::PhantomData,
         }
     }
 
-    pub fn cancel(self) -> Result<()> {
-        self.commands.send(Command::CancelOutgoingRpc(self.context))?;
+    pub fn cancel(mut self) -> Result<()> {
+        self.cancel_if_running()
+    }
+
+    fn cancel_if_running(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.commands
+            .send(Command::CancelOutgoingRpc(self.context.clone()))?;
         Ok(())
     }
 }
+
+pub struct Updates<'a, T> {
+    context: &'a mut Context,
+    // True once receiving failed. We stop then, since all further tries would fail too.
+    failed: bool,
+    phantom: marker::PhantomData<T>,
+}
+
+impl<'a, T: for<'b> serde::Deserialize<'b>> Iterator for Updates<'a, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.failed {
+            return None;
+        }
+        match self.context.recv() {
+            Ok(Some(value)) => Some(serde_json::from_value(value).map_err(|err| err.into())),
+            Ok(None) => None,
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // The client might be gone already, then there is nothing to cancel anymore.
+        let _ = self.cancel_if_running();
+    }
+}
@@ -8,6 +8,7 @@ use error::{Error, Result};
 use serde::Serialize;
 use serde_json;
 use std::sync::mpsc;
+use std::time;
 
 #[derive(Clone, Debug, PartialEq)]
 enum ContextState {
@@ -24,6 +25,10 @@ pub trait Rpc: Send + Sync {
     fn priority(&self) -> u16 {
         u16::max_value()
     }
+    /// A JSON schema for the arguments, reported by 'core.describe_rpc'.
+    fn schema(&self) -> Option<serde_json::Value> {
+        None
+    }
     fn call(&self, context: Context, args: serde_json::Value);
 }
 
@@ -48,6 +53,11 @@ impl Context {
         }
     }
 
+    /// The identifier of this call.
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
     fn update_state(&mut self) {
         match self.commands.try_recv() {
             Ok(value) => match value {
@@ -113,12 +123,35 @@ impl RpcCaller for Context {
         args: &T,
     ) -> Result<::client::rpc::client::Context> {
         self.check_liveness()?;
-        Ok(::client::rpc::client::Context::new(
+        Ok(::client::rpc::client::Context::with_parent(
+            self.rpc_loop_commands.clone(),
+            function,
+            args,
+            None,
+            Some(self.context.clone()),
+        )?)
+    }
+
+    fn call_with_timeout<T: Serialize>(
+        &mut self,
+        function: &str,
+        args: &T,
+        timeout: time::Duration,
+    ) -> Result<::client::rpc::client::Context> {
+        self.check_liveness()?;
+        Ok(::client::rpc::client::Context::with_parent(
             self.rpc_loop_commands.clone(),
             function,
-            args
+            args,
+            Some(timeout),
+            Some(self.context.clone()),
         )?)
     }
+
+    fn broadcast<T: Serialize>(&mut self, function: &str, args: &T) -> Result<()> {
+        self.check_liveness()?;
+        ::client::rpc::client::broadcast(&self.rpc_loop_commands, function, args)
+    }
 }
 
 impl Drop for Context {
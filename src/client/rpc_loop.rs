@@ -15,11 +15,15 @@ use threadpool::ThreadPool;
 pub type CommandSender = mpsc::Sender<Command>;
 pub enum Command {
     Quit,
-    NewRpc(String, Box<dyn rpc::server::Rpc>),
+    // Answers false if there already is a function with this name, which is kept then.
+    NewRpc(String, Box<dyn rpc::server::Rpc>, mpsc::Sender<bool>),
+    DeleteRpc(String),
     Received(::ipc::Message),
     OutgoingCall(String, mpsc::Sender<::rpc::Response>, ipc::Message),
     CancelOutgoingRpc(String),
     Send(::ipc::Message),
+    // Reading from the server failed and the connection was dropped.
+    Disconnected(Error),
 }
 
 struct RunningRpc {
@@ -53,7 +57,10 @@ impl spinner::Receiver<Command> for Receiver {
 
 struct Handler {
     remote_procedures: HashMap<String, Arc<Box<dyn rpc::server::Rpc>>>,
-    send_queue: mpsc::Sender<ipc::Message>,
+    // None once we lost the connection.
+    send_queue: Option<mpsc::Sender<ipc::Message>>,
+    // Why we lost the connection. Calls made after that fail with it right away.
+    disconnected: Option<::rpc::Error>,
     running_rpc_calls: HashMap<String, RunningRpc>,
     command_sender: CommandSender,
     // NOCOM(#sirver): maybe not use a channel to send data to rpcs?
@@ -67,77 +74,168 @@ impl Handler {
             remote_procedures: HashMap::new(),
             running_function_calls: HashMap::new(),
             running_rpc_calls: HashMap::new(),
-            send_queue: send_queue,
+            send_queue: Some(send_queue),
+            disconnected: None,
             command_sender: command_sender,
             // NOCOM(#sirver): that seems silly.
+            // Handlers rely on running one at a time in the order the calls arrived, e.g. the GUI
+            // sees the changes of its last edit before it handles the next one.
             thread_pool: ThreadPool::new(1),
         }
     }
+
+    // Returns false if we do not know the function.
+    fn call_function(&mut self, name: &str, context: String, args: ::serde_json::Value) -> bool {
+        let function = match self.remote_procedures.get(name) {
+            Some(function) => function.clone(),
+            None => return false,
+        };
+        let (tx, rx) = mpsc::channel();
+        self.running_rpc_calls
+            .insert(context.clone(), RunningRpc::new(tx));
+        let command_sender = self.command_sender.clone();
+        self.thread_pool.execute(move || {
+            function.call(rpc::server::Context::new(context, rx, command_sender), args);
+        });
+        true
+    }
+
+    // Drops 'message' if we lost the connection.
+    fn send(&self, message: ipc::Message) -> Result<()> {
+        if let Some(ref send_queue) = self.send_queue {
+            send_queue.send(message)?;
+        }
+        Ok(())
+    }
+
+    fn on_disconnected(&mut self, err: Error) {
+        let error = ::rpc::Error {
+            kind: ::rpc::ErrorKind::Io,
+            details: Some(::serde_json::to_value(err.to_string()).unwrap()),
+        };
+        for (context, channel) in self.running_function_calls.drain() {
+            // The caller might have dropped the RPC already.
+            let _ = channel.send(::rpc::Response {
+                context: context,
+                kind: ::rpc::ResponseKind::Last(::rpc::Result::Err(error.clone())),
+            });
+        }
+        // Nobody is going to see the answers of the functions we run.
+        for (_, function) in self.running_rpc_calls.drain() {
+            let _ = function.commands.send(rpc::server::Command::Cancel);
+        }
+        // This ends the thread writing to the server.
+        self.send_queue = None;
+        self.disconnected = Some(error);
+    }
 }
 
 impl spinner::Handler<Command> for Handler {
     fn handle(&mut self, command: Command) -> Result<spinner::Command> {
         match command {
             Command::Quit => Ok(spinner::Command::Quit),
-            Command::NewRpc(name, rpc) => {
-                self.remote_procedures.insert(name, Arc::new(rpc));
+            Command::NewRpc(name, rpc, inserted) => {
+                let is_new = !self.remote_procedures.contains_key(&name);
+                if is_new {
+                    self.remote_procedures.insert(name, Arc::new(rpc));
+                }
+                // The caller waits for this, it cannot be gone.
+                inserted.send(is_new).unwrap();
+                Ok(spinner::Command::Continue)
+            }
+            Command::DeleteRpc(name) => {
+                self.remote_procedures.remove(&name);
                 Ok(spinner::Command::Continue)
             }
             Command::Received(message) => {
                 match message {
                     ::ipc::Message::RpcCall(rpc_call) => {
-                        if let Some(function) = self.remote_procedures.get(&rpc_call.function) {
-                            let (tx, rx) = mpsc::channel();
-                            self.running_rpc_calls
-                                .insert(rpc_call.context.clone(), RunningRpc::new(tx));
-                            let command_sender = self.command_sender.clone();
-                            let function = function.clone();
-                            self.thread_pool.execute(move || {
-                                function.call(
-                                    rpc::server::Context::new(rpc_call.context, rx, command_sender),
-                                    rpc_call.args,
-                                );
-                            })
+                        let context = rpc_call.context.clone();
+                        if !self.call_function(&rpc_call.function, rpc_call.context, rpc_call.args)
+                        {
+                            // The call crossed the deletion of the function on the server, so
+                            // we let the next handler have it.
+                            self.send(::ipc::Message::RpcResponse(::rpc::Response {
+                                context: context,
+                                kind: ::rpc::ResponseKind::Last(::rpc::Result::NotHandled),
+                            }))?;
                         }
-                        // NOCOM(#sirver): return an error - though if that has happened the
-                        // server messed up too.
+                    }
+                    ::ipc::Message::RpcBroadcast(rpc_broadcast) => {
+                        // Whatever the function answers is dropped by the server.
+                        self.call_function(
+                            &rpc_broadcast.function,
+                            rpc_broadcast.context,
+                            rpc_broadcast.args,
+                        );
                     }
                     ::ipc::Message::RpcCancel(rpc_cancel) => {
-                        // NOCOM(#sirver): on drop, the rpcservercontext must delete the entry.
                         if let Some(function) = self.running_rpc_calls.remove(&rpc_cancel.context) {
                             // The function might be dead already, so we ignore errors.
                             let _ = function.commands.send(rpc::server::Command::Cancel);
                         }
                     }
+                    // The handshake is over before the rpc_loop starts.
+                    ::ipc::Message::Hello(_)
+                    | ::ipc::Message::Welcome(_)
+                    | ::ipc::Message::Refused(_) => (),
                     ipc::Message::RpcResponse(rpc_data) => {
                         // NOCOM(#sirver): if this is a streaming RPC, we should cancel the
                         // RPC.
                         // This will quietly drop any updates on functions that we no longer
                         // know/care about.
-                        self.running_function_calls
-                            .get(&rpc_data.context)
-                            .map(|channel| {
-                                // The other side of this channel might not exist anymore - we
-                                // might have dropped the RPC already. Just ignore it.
-                                let _ = channel.send(rpc_data);
-                            });
+                        let is_last = match rpc_data.kind {
+                            ::rpc::ResponseKind::Last(_) => true,
+                            ::rpc::ResponseKind::Partial(_) => false,
+                        };
+                        let channel = if is_last {
+                            self.running_function_calls.remove(&rpc_data.context)
+                        } else {
+                            self.running_function_calls.get(&rpc_data.context).cloned()
+                        };
+                        if let Some(channel) = channel {
+                            // The other side of this channel might not exist anymore - we
+                            // might have dropped the RPC already. Just ignore it.
+                            let _ = channel.send(rpc_data);
+                        }
                     }
                 }
                 Ok(spinner::Command::Continue)
             }
             Command::Send(message) => {
-                self.send_queue.send(message)?;
+                // Once a function we run sent its last response, it is done.
+                if let ::ipc::Message::RpcResponse(::rpc::Response {
+                    ref context,
+                    kind: ::rpc::ResponseKind::Last(_),
+                }) = message
+                {
+                    self.running_rpc_calls.remove(context);
+                }
+                self.send(message)?;
                 Ok(spinner::Command::Continue)
             }
             Command::OutgoingCall(context, tx, message) => {
+                if let Some(ref error) = self.disconnected {
+                    // The caller might have dropped the RPC already.
+                    let _ = tx.send(::rpc::Response {
+                        context: context,
+                        kind: ::rpc::ResponseKind::Last(::rpc::Result::Err(error.clone())),
+                    });
+                    return Ok(spinner::Command::Continue);
+                }
                 self.running_function_calls.insert(context, tx);
                 // NOCOM(#sirver): can the message be constructed here?
-                self.send_queue.send(message)?;
+                self.send(message)?;
                 Ok(spinner::Command::Continue)
             }
             Command::CancelOutgoingRpc(context) => {
+                self.running_function_calls.remove(&context);
                 let msg = ::ipc::Message::RpcCancel(::rpc::Cancel { context: context });
-                self.send_queue.send(msg)?;
+                self.send(msg)?;
+                Ok(spinner::Command::Continue)
+            }
+            Command::Disconnected(err) => {
+                self.on_disconnected(err);
                 Ok(spinner::Command::Continue)
             }
         }
@@ -4,6 +4,7 @@
 
 /// Errors for use with Swiboe.
 use mio;
+use rpc;
 use serde_json;
 use std::error;
 use std::fmt;
@@ -20,16 +21,35 @@ pub enum Error {
     JsonParsing(serde_json::error::Error),
     RpcDone,
     InvalidUtf8,
+    Timeout,
+    /// The RPC failed with this error.
+    Rpc(rpc::Error),
+    /// No handler of the RPC handled it.
+    NotHandled,
+    /// The server did not let us connect, for the given reason.
+    Refused(String),
+    /// Our peer sent a frame that is too big or that does not contain a message.
+    InvalidFrame(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let error = match *self {
+            Error::Rpc(ref err) => {
+                return match err.details {
+                    Some(ref details) => write!(f, "RPC failed: {:?} ({})", err.kind, details),
+                    None => write!(f, "RPC failed: {:?}", err.kind),
+                };
+            }
             Error::Disconnected => "Channel or Socket is disconnected.",
             Error::Io(_) => "IO error",
             Error::JsonParsing(_) => "Error in parsing JSON",
             Error::RpcDone => "RPC is already finished or cancelled.",
             Error::InvalidUtf8 => "Invalid utf-8 string encountered.",
+            Error::Timeout => "Timed out waiting for a response.",
+            Error::NotHandled => "RPC was not handled.",
+            Error::Refused(_) => "Server refused the connection.",
+            Error::InvalidFrame(_) => "Received an invalid frame.",
         };
         write!(f, "{}", error)
     }
@@ -43,6 +63,11 @@ impl error::Error for Error {
             Error::JsonParsing(ref e) => e.description(),
             Error::RpcDone => "RPC is already finished or cancelled.",
             Error::InvalidUtf8 => "Invalid utf-8 string encountered.",
+            Error::Timeout => "Timed out waiting for a response.",
+            Error::Rpc(_) => "RPC failed",
+            Error::NotHandled => "RPC was not handled.",
+            Error::Refused(_) => "Server refused the connection.",
+            Error::InvalidFrame(_) => "Received an invalid frame.",
         }
     }
 }
@@ -53,6 +78,12 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<rpc::Error> for Error {
+    fn from(error: rpc::Error) -> Self {
+        Error::Rpc(error)
+    }
+}
+
 impl From<::std::str::Utf8Error> for Error {
     fn from(_: ::std::str::Utf8Error) -> Self {
         Error::InvalidUtf8
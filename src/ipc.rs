@@ -2,23 +2,104 @@
 // Licensed under the Apache License, Version 2.0. See LICENSE.txt
 // in the project root for license information.
 
+use error::{Error, Result};
 use mio::{TryRead, TryWrite};
+use rmp_serde;
 use rpc;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::io::{Read, Write};
-use Result;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Clients need to talk the same version as the server, otherwise they are refused.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional parts of the protocol that this implementation supports.
+pub const FEATURES: &'static [&'static str] = &["broadcast", "deadlines", "introspection"];
+
+/// Frames that announce more bytes than this are rejected, unless the reader is told otherwise.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+// How much we try to read from a non-blocking socket at once.
+const READ_CHUNK_SIZE: usize = 4096;
+
+pub fn features() -> Vec<String> {
+    FEATURES.iter().map(|feature| feature.to_string()).collect()
+}
+
+/// Reads the shared secret that clients present to the server from 'path'. Surrounding whitespace
+/// is ignored, so that the file can be written with 'echo'.
+pub fn read_auth_token(path: &Path) -> Result<String> {
+    let mut content = String::new();
+    fs::File::open(path)?.read_to_string(&mut content)?;
+    let token = content.trim();
+    if token.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not contain a token", path.display()),
+        )
+        .into());
+    }
+    Ok(token.to_string())
+}
+
+/// How messages are serialized on the wire. Every connection starts out with JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+}
+
+/// The first message a client must send. It also picks an encoding for the rest of the
+/// connection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hello {
+    pub version: u32,
+    /// Describes the client to humans, e.g. the name of the program.
+    pub name: String,
+    pub pid: u32,
+    pub features: Vec<String>,
+    /// The encodings the client can use, the preferred one first.
+    pub encodings: Vec<Encoding>,
+    /// Servers can require this for TCP connections, see 'read_auth_token'.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+}
+
+/// The answer of the server to 'Hello'. Everything after it is sent in 'encoding', in both
+/// directions.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Welcome {
+    pub version: u32,
+    pub features: Vec<String>,
+    pub encoding: Encoding,
+}
+
+/// The answer of the server to a 'Hello' it cannot work with. It is sent in JSON and the server
+/// closes the connection right after.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Refused {
+    pub version: u32,
+    pub reason: String,
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
     RpcCall(rpc::Call),
     RpcResponse(rpc::Response),
     RpcCancel(rpc::Cancel),
+    RpcBroadcast(rpc::Broadcast),
+    Hello(Hello),
+    Welcome(Welcome),
+    Refused(Refused),
 }
 
 pub struct Reader<T: Read> {
     pub socket: T,
     buffer: Vec<u8>,
+    encoding: Encoding,
+    max_frame_size: usize,
 }
 
 fn parse_length(buf: &[u8]) -> usize {
@@ -28,9 +109,16 @@ fn parse_length(buf: &[u8]) -> usize {
         | ((buf[0] as usize) << 0)
 }
 
-// Tries to parse a JSON message into an ipc::Message struct.
-fn to_message(data: &[u8]) -> Result<Message> {
-    let message: Message = serde_json::from_slice(data)?;
+fn invalid_frame<E: ToString>(error: E) -> Error {
+    Error::InvalidFrame(error.to_string())
+}
+
+// Tries to parse a message in 'encoding' into an ipc::Message struct.
+fn to_message(data: &[u8], encoding: Encoding) -> Result<Message> {
+    let message: Message = match encoding {
+        Encoding::Json => serde_json::from_slice(data).map_err(invalid_frame)?,
+        Encoding::MessagePack => rmp_serde::from_slice(data).map_err(invalid_frame)?,
+    };
     return Ok(message);
 }
 
@@ -39,40 +127,66 @@ impl<T: Read> Reader<T> {
         Reader {
             socket: socket,
             buffer: Vec::with_capacity(1024),
+            encoding: Encoding::Json,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
         }
     }
 
+    /// Frames bigger than 'max_frame_size' bytes are an error from now on.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    // The length of the next frame, if our peer is allowed to send that much.
+    fn frame_length(&self, header: &[u8]) -> Result<usize> {
+        let msg_len = parse_length(header);
+        if msg_len > self.max_frame_size {
+            return Err(invalid_frame(format!(
+                "frame of {} bytes is bigger than the maximum of {} bytes",
+                msg_len, self.max_frame_size
+            )));
+        }
+        Ok(msg_len)
+    }
+
+    /// All messages after the current one are expected in 'encoding'.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
     /// Read one full message - this expects the underlying socket to be blocking.
     pub fn read_message(&mut self) -> Result<Message> {
         let mut size_buf = [0u8; 4];
         self.socket.read_exact(&mut size_buf)?;
-        let msg_len = parse_length(&size_buf);
+        let msg_len = self.frame_length(&size_buf)?;
 
-        self.buffer.reserve(msg_len);
-        unsafe {
-            self.buffer.set_len(msg_len);
-        }
+        self.buffer.clear();
+        self.buffer.resize(msg_len, 0);
         self.socket.read_exact(&mut self.buffer)?;
-        to_message(&self.buffer)
+        to_message(&self.buffer, self.encoding)
     }
 
-    /// Read all data currently available on the socket and returns the next full message that is
-    /// available or None if there is no full one.
+    /// Reads from the socket until the next message is complete and returns it. Returns None once
+    /// the socket has no more data for now, but no full message came together.
     pub fn try_read_message(&mut self) -> Result<Option<Message>> {
-        // This might reallocate 'buffer' if it is too small.
-        self.socket.try_read_buf(&mut self.buffer)?;
-        if self.buffer.len() < 4 {
-            return Ok(None);
-        }
+        loop {
+            if self.buffer.len() >= 4 {
+                let msg_len = self.frame_length(&self.buffer[..4])?;
+                if self.buffer.len() >= msg_len + 4 {
+                    let message = to_message(&self.buffer[4..4 + msg_len], self.encoding);
+                    self.buffer.drain(..4 + msg_len);
+                    return message.map(|message| Some(message));
+                }
+            }
 
-        let msg_len = parse_length(&self.buffer[..4]);
-        if self.buffer.len() < msg_len + 4 {
-            return Ok(None);
+            // We only stop once the socket would block, since streams like TLS can have data
+            // buffered that the event loop does not know about.
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            match self.socket.try_read(&mut chunk)? {
+                None | Some(0) => return Ok(None),
+                Some(num_read) => self.buffer.extend_from_slice(&chunk[..num_read]),
+            }
         }
-        let message = to_message(&self.buffer[4..4 + msg_len]);
-        self.buffer.drain(..4 + msg_len);
-
-        message.map(|message| Some(message))
     }
 }
 
@@ -81,6 +195,7 @@ pub struct Writer<T: Write> {
     // popped.
     num_written: usize,
     to_write: Vec<Vec<u8>>,
+    encoding: Encoding,
     pub socket: T,
 }
 
@@ -89,8 +204,13 @@ pub enum WriterState {
     AllWritten,
 }
 
-fn encode(message: &Message) -> Result<(Vec<u8>, Vec<u8>)> {
-    let buffer = serde_json::to_vec(message)?;
+fn encode(message: &Message, encoding: Encoding) -> Result<(Vec<u8>, Vec<u8>)> {
+    let buffer = match encoding {
+        Encoding::Json => serde_json::to_vec(message)?,
+        // Field names are kept, so that optional fields can be left out.
+        Encoding::MessagePack => rmp_serde::to_vec_named(message)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+    };
     let len = vec![
         (buffer.len() >> 0) as u8,
         (buffer.len() >> 8) as u8,
@@ -106,11 +226,17 @@ impl<T: Write> Writer<T> {
             socket: socket,
             num_written: 0,
             to_write: Vec::new(),
+            encoding: Encoding::Json,
         }
     }
 
+    /// All messages written or queued from now on are sent in 'encoding'.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
     pub fn write_message(&mut self, message: &Message) -> Result<()> {
-        let (len, buffer) = encode(message)?;
+        let (len, buffer) = encode(message, self.encoding)?;
         self.socket.write_all(&len)?;
         self.socket.write_all(&buffer)?;
         Ok(())
@@ -118,14 +244,21 @@ impl<T: Write> Writer<T> {
 
     pub fn queue_message(&mut self, message: &Message) {
         // NOCOM(#sirver): should not unwrap
-        let (len, buffer) = encode(message).unwrap();
+        let (len, buffer) = encode(message, self.encoding).unwrap();
         self.to_write.push(len);
         self.to_write.push(buffer);
     }
 
     pub fn try_write(&mut self) -> Result<WriterState> {
         if self.to_write.is_empty() {
-            return Ok(WriterState::AllWritten);
+            // Streams like TLS might still hold on to some of the data.
+            return match self.socket.flush() {
+                Ok(()) => Ok(WriterState::AllWritten),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    Ok(WriterState::MoreToWrite)
+                }
+                Err(err) => Err(err.into()),
+            };
         }
 
         if let Some(num_written) = self
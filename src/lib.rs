@@ -4,6 +4,11 @@
 
 extern crate libc;
 extern crate mio;
+extern crate notify;
+extern crate rmp_serde;
+extern crate ropey;
+extern crate rustls;
+extern crate rustls_pemfile;
 extern crate serde;
 extern crate serde_json;
 extern crate tempdir;
@@ -30,11 +35,12 @@ macro_rules! try_rpc {
 
 pub mod client;
 pub mod error;
-mod ipc;
+pub mod ipc;
 pub mod plugin;
 pub mod rpc;
 pub mod server;
 pub mod spinner;
 pub mod testing;
+pub mod tls;
 
 pub use error::{Error, Result};
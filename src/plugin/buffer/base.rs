@@ -3,18 +3,45 @@
 // in the project root for license information.
 use client;
 use client::RpcCaller;
+use notify::{self, Watcher};
+use plugin::buffer::disk::{self, DiskState, Format};
+use plugin::buffer::history::{Edit, History};
+use ropey::Rope;
 use rpc;
 use serde::{Deserialize, Serialize};
 use serde_json;
 use std::collections::HashMap;
-use std::io;
+use std::fs;
+use std::io::{self, Write};
 use std::ops;
+use std::path;
 use std::result;
 use std::string;
+use std::sync::mpsc;
+use std::time;
+use uuid::Uuid;
+
+pub const FILE_PREFIX: &str = "file://";
+/// Buffers that have never been saved. 'untitled:' alone picks a fresh name.
+pub const UNTITLED_PREFIX: &str = "untitled:";
+/// Throwaway buffers that never count as having unsaved changes.
+pub const SCRATCH_PREFIX: &str = "scratch:";
+/// The data piped into the server process.
+pub const STDIN_URI: &str = "stdin:";
 
 #[derive(Debug)]
 pub enum BufferError {
     UnknownBuffer,
+    InvalidPosition,
+    InvalidRange,
+    NothingToUndo,
+    NothingToRedo,
+    NoTransaction,
+    TransactionRunning,
+    NoUri,
+    InvalidUri,
+    Loading,
+    Io(io::Error),
 }
 
 impl From<BufferError> for rpc::Error {
@@ -22,16 +49,32 @@ impl From<BufferError> for rpc::Error {
         use rpc::ErrorKind::*;
 
         let (kind, details) = match error {
-            BufferError::UnknownBuffer => (InvalidArgs, format!("unknown_buffer")),
+            BufferError::UnknownBuffer => (InvalidArgs, "unknown_buffer".to_string()),
+            BufferError::InvalidPosition => (InvalidArgs, "invalid_position".to_string()),
+            BufferError::InvalidRange => (InvalidArgs, "invalid_range".to_string()),
+            BufferError::NothingToUndo => (InvalidArgs, "nothing_to_undo".to_string()),
+            BufferError::NothingToRedo => (InvalidArgs, "nothing_to_redo".to_string()),
+            BufferError::NoTransaction => (InvalidArgs, "no_transaction".to_string()),
+            BufferError::TransactionRunning => (InvalidArgs, "transaction_running".to_string()),
+            BufferError::NoUri => (InvalidArgs, "no_uri".to_string()),
+            BufferError::InvalidUri => (InvalidArgs, "invalid_uri".to_string()),
+            BufferError::Loading => (InvalidArgs, "loading".to_string()),
+            BufferError::Io(error) => return error.into(),
         };
 
         rpc::Error {
-            kind: kind,
+            kind,
             details: Some(serde_json::to_value(&details).unwrap()),
         }
     }
 }
 
+impl From<io::Error> for BufferError {
+    fn from(error: io::Error) -> Self {
+        BufferError::Io(error)
+    }
+}
+
 impl From<io::Error> for rpc::Error {
     fn from(error: io::Error) -> Self {
         let details = match error.kind() {
@@ -70,14 +113,108 @@ pub struct BufferDeleted {
     pub buffer_index: usize,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct BufferSaved {
+    pub buffer_index: usize,
+    pub uri: String,
+    /// The revision of the buffer that was written.
+    pub revision: u64,
+}
+
+/// Broadcasted when the file a buffer was loaded from has been changed by somebody else.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct BufferFileChangedOnDisk {
+    pub buffer_index: usize,
+    pub uri: String,
+    /// True if the file does not exist anymore.
+    pub deleted: bool,
+    /// True if the buffer was clean and has been replaced by the new content of the file.
+    pub reloaded: bool,
+}
+
+/// A location inside of a buffer.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Position {
+    /// 0 based byte offset into the buffer. Must fall on a character boundary.
+    Byte(usize),
+
+    /// 0 based character offset into the buffer.
+    Char(usize),
+
+    /// 0 based line index and 0 based glyph index into the line. A multibyte character only
+    /// counts as one here. The column might point right after the last character of the line.
+    LineColumn {
+        line_index: usize,
+        column_index: usize,
+    },
+}
+
+/// A resolved 'Position' that carries all ways of addressing a location in a buffer.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Location {
+    pub byte_index: usize,
+    pub char_index: usize,
+    pub line_index: usize,
+    pub column_index: usize,
+}
+
+/// Broadcasted whenever the content of a buffer changes. The text between 'start' and 'end' (as
+/// they were before the change) was replaced by 'text'.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct BufferChanged {
+    pub buffer_index: usize,
+    /// The revision of the buffer after this change. It increases by one with every change.
+    pub revision: u64,
+    pub start: Location,
+    pub end: Location,
+    pub text: String,
+}
+
+/// Broadcasted instead of 'BufferChanged' when the whole content of a buffer was replaced at once,
+/// e.g. because it finished loading. The content can be huge, so it is not part of the event.
+/// Subscribers get it through 'buffer.get_content' or 'buffer.get_lines' if they need it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct BufferReloaded {
+    pub buffer_index: usize,
+    /// The revision of the buffer with the new content.
+    pub revision: u64,
+}
+
+// A change that has been applied to a buffer, before we know which buffer it was.
+pub struct Change {
+    pub revision: u64,
+    pub start: Location,
+    pub end: Location,
+    pub text: String,
+}
+
 pub struct Buffer {
-    // TODO(sirver): This should probably be something more clever, like a rope or a gap buffer.
-    content: String,
+    content: Rope,
+    revision: u64,
+    history: History,
+    uri: Option<String>,
+    dirty: bool,
+    // The state in the history that was last written to 'uri'.
+    saved_state: usize,
+    // The state of the file at 'uri' when we last read or wrote it.
+    disk_state: Option<DiskState>,
+    auto_reload: bool,
+    format: Format,
+    // True while the content is still being read from 'uri'.
+    loading: bool,
+    // When the running transaction is considered abandoned, because its client went away
+    // without ending it. Every edit pushes this back.
+    transaction_timeout: time::Duration,
+    transaction_expires: Option<time::Instant>,
 }
 
+/// How long a transaction stays open without anything happening on its buffer, unless the client
+/// asks for something else in 'buffer.begin_transaction'.
+pub const DEFAULT_TRANSACTION_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
 impl string::ToString for Buffer {
     fn to_string(&self) -> String {
-        self.content.clone()
+        self.content.to_string()
     }
 }
 
@@ -87,7 +224,337 @@ impl Buffer {
     }
 
     pub fn from_string(content: String) -> Self {
-        Buffer { content: content }
+        Buffer {
+            content: Rope::from_str(&content),
+            revision: 0,
+            history: History::new(),
+            uri: None,
+            dirty: false,
+            saved_state: 0,
+            disk_state: None,
+            auto_reload: false,
+            format: Format::new(),
+            loading: false,
+            transaction_timeout: DEFAULT_TRANSACTION_TIMEOUT,
+            transaction_expires: None,
+        }
+    }
+
+    /// An empty buffer for 'uri' that cannot be changed until its content was read by
+    /// 'BuffersManager::finish_loading'.
+    pub fn loading(uri: String) -> Self {
+        let mut buffer = Self::new();
+        buffer.uri = Some(uri);
+        buffer.loading = true;
+        buffer
+    }
+
+    /// Converts 'position' into a char index into the rope.
+    fn char_index(&self, position: Position) -> result::Result<usize, BufferError> {
+        match position {
+            Position::Byte(byte_index) => {
+                let char_index = self
+                    .content
+                    .try_byte_to_char(byte_index)
+                    .map_err(|_| BufferError::InvalidPosition)?;
+                if self.content.char_to_byte(char_index) != byte_index {
+                    return Err(BufferError::InvalidPosition);
+                }
+                Ok(char_index)
+            }
+            Position::Char(char_index) => {
+                if char_index > self.content.len_chars() {
+                    return Err(BufferError::InvalidPosition);
+                }
+                Ok(char_index)
+            }
+            Position::LineColumn {
+                line_index,
+                column_index,
+            } => {
+                let line = self
+                    .content
+                    .get_line(line_index)
+                    .ok_or(BufferError::InvalidPosition)?;
+                let mut line_len = line.len_chars();
+                while line_len > 0 && matches!(line.char(line_len - 1), '\n' | '\r') {
+                    line_len -= 1;
+                }
+                if column_index > line_len {
+                    return Err(BufferError::InvalidPosition);
+                }
+                Ok(self.content.line_to_char(line_index) + column_index)
+            }
+        }
+    }
+
+    fn char_range(
+        &self,
+        start: Position,
+        end: Position,
+    ) -> result::Result<ops::Range<usize>, BufferError> {
+        let start = self.char_index(start)?;
+        let end = self.char_index(end)?;
+        if start > end {
+            return Err(BufferError::InvalidRange);
+        }
+        Ok(start..end)
+    }
+
+    fn location(&self, char_index: usize) -> Location {
+        let line_index = self.content.char_to_line(char_index);
+        Location {
+            byte_index: self.content.char_to_byte(char_index),
+            char_index,
+            line_index,
+            column_index: char_index - self.content.line_to_char(line_index),
+        }
+    }
+
+    /// Returns up to 'count' lines starting with line 'first' without their line endings. Less
+    /// lines are returned if the buffer ends before.
+    pub fn lines(&self, first: usize, count: usize) -> result::Result<Vec<String>, BufferError> {
+        let len_lines = self.content.len_lines();
+        if first > len_lines {
+            return Err(BufferError::InvalidPosition);
+        }
+        let last = len_lines.min(first.saturating_add(count));
+        let lines = (first..last)
+            .map(|line_index| {
+                let mut line = self.content.line(line_index).to_string();
+                while line.ends_with('\n') || line.ends_with('\r') {
+                    line.pop();
+                }
+                line
+            })
+            .collect();
+        Ok(lines)
+    }
+
+    /// Returns the text between 'start' and 'end' together with where exactly that is.
+    pub fn range(
+        &self,
+        start: Position,
+        end: Position,
+    ) -> result::Result<(Location, Location, String), BufferError> {
+        let range = self.char_range(start, end)?;
+        let text = self.content.slice(range.clone()).to_string();
+        Ok((self.location(range.start), self.location(range.end), text))
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.content.len_bytes()
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.content.len_lines()
+    }
+
+    /// How the buffer is stored on disk.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    /// The URI this buffer was loaded from or last saved to.
+    pub fn uri(&self) -> Option<&str> {
+        self.uri.as_ref().map(|uri| uri as &str)
+    }
+
+    /// Sets the URI without touching the file it points to.
+    pub fn set_uri(&mut self, uri: String) {
+        self.uri = Some(uri);
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading
+    }
+
+    /// True if the buffer was changed since it was last saved. Scratch buffers are never dirty.
+    pub fn is_dirty(&self) -> bool {
+        let is_scratch = self
+            .uri()
+            .is_some_and(|uri| uri.starts_with(SCRATCH_PREFIX));
+        self.dirty && !is_scratch
+    }
+
+    /// Writes the buffer to 'path', which must not be URI, but a plain path. The content is first
+    /// written to a temporary file that is then renamed, so that 'path' always contains either the
+    /// old or the new content. The permissions of an existing file are kept.
+    pub fn write_to_file(&self, path: &path::Path) -> io::Result<DiskState> {
+        let file_name = path
+            .file_name()
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+        let temp_path = path.with_file_name(format!(
+            ".{}.{}.swiboe",
+            file_name.to_string_lossy(),
+            Uuid::new_v4().to_hyphenated_string()
+        ));
+
+        let result = self.write_to_temp_file(&temp_path, path);
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    fn write_to_temp_file(
+        &self,
+        temp_path: &path::Path,
+        path: &path::Path,
+    ) -> io::Result<DiskState> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(temp_path)?;
+        let mut hash = disk::Hash::new();
+        {
+            let mut writer = io::BufWriter::new(&mut file);
+            let mut data = self.format.bom_bytes().to_vec();
+            for chunk in self.content.chunks() {
+                self.format.encode(chunk, &mut data)?;
+                writer.write_all(&data)?;
+                hash.update(&data);
+                data.clear();
+            }
+            writer.write_all(&data)?;
+            hash.update(&data);
+            writer.flush()?;
+        }
+        file.sync_all()?;
+
+        match fs::metadata(path) {
+            Ok(metadata) => fs::set_permissions(temp_path, metadata.permissions())?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        fs::rename(temp_path, path)?;
+
+        let metadata = fs::metadata(path)?;
+        Ok(DiskState {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            hash: hash.finish(),
+        })
+    }
+
+    /// Remembers that the current state of the buffer has been saved to 'uri' and that the file
+    /// there now looks like 'disk_state'.
+    pub fn mark_saved(&mut self, uri: String, disk_state: DiskState) {
+        self.uri = Some(uri);
+        self.dirty = false;
+        self.saved_state = self.history.current();
+        self.disk_state = Some(disk_state);
+    }
+
+    /// If set, the buffer is reloaded when its file changes on disk and it has no unsaved changes.
+    pub fn set_auto_reload(&mut self, auto_reload: bool) {
+        self.auto_reload = auto_reload;
+    }
+
+    // Applies 'edit' without recording it in the history.
+    fn apply(&mut self, edit: &Edit) -> Change {
+        let end = edit.start + edit.removed.chars().count();
+        let change = Change {
+            revision: self.revision + 1,
+            start: self.location(edit.start),
+            end: self.location(end),
+            text: edit.inserted.clone(),
+        };
+        self.content.remove(edit.start..end);
+        self.content.insert(edit.start, &edit.inserted);
+        self.revision = change.revision;
+        change
+    }
+
+    /// Replaces the text between 'start' and 'end' with 'text'. Inserting and deleting are special
+    /// cases of this.
+    pub fn replace(
+        &mut self,
+        start: Position,
+        end: Position,
+        text: &str,
+    ) -> result::Result<Change, BufferError> {
+        if self.loading {
+            return Err(BufferError::Loading);
+        }
+        let range = self.char_range(start, end)?;
+        self.touch_transaction();
+        let edit = Edit {
+            start: range.start,
+            removed: self.content.slice(range).to_string(),
+            inserted: text.into(),
+        };
+        let change = self.apply(&edit);
+        self.history.record(edit);
+        self.dirty = true;
+        Ok(change)
+    }
+
+    pub fn undo(&mut self) -> result::Result<Vec<Change>, BufferError> {
+        self.expire_transaction();
+        if self.history.in_transaction() {
+            return Err(BufferError::TransactionRunning);
+        }
+        let edits = self.history.undo().ok_or(BufferError::NothingToUndo)?;
+        let changes = edits.iter().map(|edit| self.apply(edit)).collect();
+        self.dirty = self.history.current() != self.saved_state;
+        Ok(changes)
+    }
+
+    pub fn redo(&mut self) -> result::Result<Vec<Change>, BufferError> {
+        self.expire_transaction();
+        if self.history.in_transaction() {
+            return Err(BufferError::TransactionRunning);
+        }
+        let edits = self.history.redo().ok_or(BufferError::NothingToRedo)?;
+        let changes = edits.iter().map(|edit| self.apply(edit)).collect();
+        self.dirty = self.history.current() != self.saved_state;
+        Ok(changes)
+    }
+
+    /// Starts a transaction that is abandoned if nothing happens on this buffer for 'timeout'.
+    pub fn begin_transaction(&mut self, timeout: time::Duration) {
+        self.expire_transaction();
+        self.history.begin_transaction();
+        self.transaction_timeout = timeout;
+        self.touch_transaction();
+    }
+
+    pub fn end_transaction(&mut self) -> result::Result<(), BufferError> {
+        self.expire_transaction();
+        if !self.history.end_transaction() {
+            return Err(BufferError::NoTransaction);
+        }
+        self.touch_transaction();
+        Ok(())
+    }
+
+    // Abandons the running transaction if nothing happened for too long.
+    fn expire_transaction(&mut self) {
+        if let Some(expires) = self.transaction_expires {
+            if expires <= time::Instant::now() {
+                self.history.abandon_transactions();
+                self.transaction_expires = None;
+            }
+        }
+    }
+
+    // Pushes the expiry of the running transaction back, after it was used.
+    fn touch_transaction(&mut self) {
+        self.expire_transaction();
+        self.transaction_expires = if self.history.in_transaction() {
+            Some(time::Instant::now() + self.transaction_timeout)
+        } else {
+            None
+        };
     }
 }
 
@@ -95,14 +562,68 @@ pub struct BuffersManager {
     next_buffer_index: usize,
     buffers: HashMap<usize, Buffer>,
     client: client::ThinClient,
+    // None if the platform does not allow us to watch files.
+    watcher: Option<notify::RecommendedWatcher>,
+    // We watch directories instead of files, so that we also notice files being replaced through
+    // a rename. Maps to the number of buffers that need the directory to be watched.
+    watched_directories: HashMap<path::PathBuf, usize>,
 }
 
 impl BuffersManager {
-    pub fn new(client: client::ThinClient) -> Self {
+    pub fn new(
+        client: client::ThinClient,
+        file_events: mpsc::Sender<notify::DebouncedEvent>,
+    ) -> Self {
         BuffersManager {
             next_buffer_index: 0,
             buffers: HashMap::new(),
             client: client,
+            watcher: notify::watcher(file_events, time::Duration::from_millis(50)).ok(),
+            watched_directories: HashMap::new(),
+        }
+    }
+
+    fn watch(&mut self, uri: Option<&str>) {
+        let directory = match uri.and_then(disk::file_path) {
+            Some(path) => match path.parent() {
+                Some(directory) => directory.to_path_buf(),
+                None => return,
+            },
+            None => return,
+        };
+        let count = self
+            .watched_directories
+            .entry(directory.clone())
+            .or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            if let Some(ref mut watcher) = self.watcher {
+                // If we cannot watch, we simply do not notice changes.
+                let _ = watcher.watch(&directory, notify::RecursiveMode::NonRecursive);
+            }
+        }
+    }
+
+    fn unwatch(&mut self, uri: Option<&str>) {
+        let directory = match uri.and_then(disk::file_path) {
+            Some(path) => match path.parent() {
+                Some(directory) => directory.to_path_buf(),
+                None => return,
+            },
+            None => return,
+        };
+        let still_watched = match self.watched_directories.get_mut(&directory) {
+            Some(count) => {
+                *count -= 1;
+                *count > 0
+            }
+            None => return,
+        };
+        if !still_watched {
+            self.watched_directories.remove(&directory);
+            if let Some(ref mut watcher) = self.watcher {
+                let _ = watcher.unwatch(&directory);
+            }
         }
     }
 
@@ -110,11 +631,11 @@ impl BuffersManager {
         let current_buffer_index = self.next_buffer_index;
         self.next_buffer_index += 1;
 
+        self.watch(buffer.uri());
         self.buffers.insert(current_buffer_index, buffer);
 
         // NOCOM(#sirver): new is not good. should be create.
-        // Fire the callback, but we do not wait for it's conclusion.
-        let _ = self.client.call(
+        self.publish(
             "on.buffer.new",
             &BufferCreated {
                 buffer_index: current_buffer_index,
@@ -124,13 +645,13 @@ impl BuffersManager {
     }
 
     pub fn delete_buffer(&mut self, buffer_index: usize) -> result::Result<(), BufferError> {
-        self
+        let buffer = self
             .buffers
             .remove(&buffer_index)
             .ok_or(BufferError::UnknownBuffer)?;
+        self.unwatch(buffer.uri());
 
-        // Fire the callback, but we do not wait for it's conclusion.
-        let _ = self.client.call(
+        self.publish(
             "on.buffer.deleted",
             &BufferDeleted {
                 buffer_index: buffer_index,
@@ -140,10 +661,260 @@ impl BuffersManager {
         Ok(())
     }
 
+    /// Returns the index of a buffer that has 'uri'.
+    pub fn find_by_uri(&self, uri: &str) -> Option<usize> {
+        self.buffers
+            .iter()
+            .find(|&(_, buffer)| buffer.uri() == Some(uri))
+            .map(|(index, _)| *index)
+    }
+
+    /// Returns a URI starting with 'prefix' that no buffer uses.
+    pub fn unused_uri(&self, prefix: &str) -> String {
+        let mut number = self.next_buffer_index;
+        loop {
+            let uri = format!("{}{}", prefix, number);
+            if self.find_by_uri(&uri).is_none() {
+                return uri;
+            }
+            number += 1;
+        }
+    }
+
     pub fn get(&self, index: usize) -> result::Result<&Buffer, BufferError> {
         let buffer = self.buffers.get(&index).ok_or(BufferError::UnknownBuffer)?;
         Ok(buffer)
     }
+
+    pub fn get_mut(&mut self, index: usize) -> result::Result<&mut Buffer, BufferError> {
+        let buffer = self
+            .buffers
+            .get_mut(&index)
+            .ok_or(BufferError::UnknownBuffer)?;
+        Ok(buffer)
+    }
+
+    /// Replaces the text between 'start' and 'end' in the buffer and lets everybody know about
+    /// the change. Returns the new revision of the buffer.
+    pub fn replace(
+        &mut self,
+        buffer_index: usize,
+        start: Position,
+        end: Position,
+        text: &str,
+    ) -> result::Result<u64, BufferError> {
+        let change = self.get_mut(buffer_index)?.replace(start, end, text)?;
+        let revision = change.revision;
+        self.publish_changes(buffer_index, vec![change]);
+        Ok(revision)
+    }
+
+    /// Undoes the last change or transaction. Returns the new revision of the buffer.
+    pub fn undo(&mut self, buffer_index: usize) -> result::Result<u64, BufferError> {
+        let changes = self.get_mut(buffer_index)?.undo()?;
+        self.publish_changes(buffer_index, changes);
+        Ok(self.get(buffer_index)?.revision())
+    }
+
+    /// Redoes the last undone change or transaction. Returns the new revision of the buffer.
+    pub fn redo(&mut self, buffer_index: usize) -> result::Result<u64, BufferError> {
+        let changes = self.get_mut(buffer_index)?.redo()?;
+        self.publish_changes(buffer_index, changes);
+        Ok(self.get(buffer_index)?.revision())
+    }
+
+    /// Writes the buffer to 'uri', which must be a 'file://' URI, and lets everybody know about
+    /// it. Returns the revision that was saved.
+    pub fn save(&mut self, buffer_index: usize, uri: String) -> result::Result<u64, BufferError> {
+        let path = disk::file_path(&uri).ok_or(BufferError::InvalidUri)?;
+        let path = disk::canonical_path(&path)?;
+        let uri = disk::file_uri(&path);
+        let (revision, old_uri) = {
+            let buffer = self.get_mut(buffer_index)?;
+            if buffer.loading {
+                return Err(BufferError::Loading);
+            }
+            let disk_state = buffer.write_to_file(&path)?;
+            let old_uri = buffer.uri.clone();
+            buffer.mark_saved(uri.clone(), disk_state);
+            (buffer.revision(), old_uri)
+        };
+        if old_uri.as_ref() != Some(&uri) {
+            self.unwatch(old_uri.as_ref().map(|uri| uri as &str));
+            self.watch(Some(&uri));
+        }
+
+        self.publish(
+            "on.buffer.saved",
+            &BufferSaved {
+                buffer_index,
+                uri,
+                revision,
+            },
+        );
+        Ok(revision)
+    }
+
+    /// Fills a buffer created by 'Buffer::loading' with the content read from its file and lets
+    /// everybody know through 'on.buffer.reloaded'. Returns the new revision of the buffer.
+    pub fn finish_loading(
+        &mut self,
+        buffer_index: usize,
+        content: String,
+        format: Format,
+        disk_state: DiskState,
+    ) -> result::Result<u64, BufferError> {
+        let revision = {
+            let buffer = self.get_mut(buffer_index)?;
+            let uri = buffer.uri.clone().ok_or(BufferError::NoUri)?;
+            let change = buffer.apply(&Edit {
+                start: 0,
+                removed: String::new(),
+                inserted: content,
+            });
+            buffer.set_format(format);
+            buffer.mark_saved(uri, disk_state);
+            buffer.loading = false;
+            change.revision
+        };
+
+        self.publish(
+            "on.buffer.reloaded",
+            &BufferReloaded {
+                buffer_index,
+                revision,
+            },
+        );
+        Ok(revision)
+    }
+
+    /// Checks if the file at 'path' still looks like what we think all buffers that were loaded
+    /// from it contain and lets everybody know if not.
+    pub fn check_file_on_disk(&mut self, path: &path::Path) {
+        // The paths of our buffers are canonical, see 'disk::canonical_path'.
+        let path = &disk::canonical_path(path).unwrap_or_else(|_| path.to_path_buf());
+        let buffer_indices: Vec<usize> = self
+            .buffers
+            .iter()
+            .filter(|&(_, buffer)| {
+                !buffer.loading
+                    && buffer
+                        .uri()
+                        .and_then(disk::file_path)
+                        .as_ref()
+                        .map(|p| p as &path::Path)
+                        == Some(path)
+            })
+            .map(|(buffer_index, _)| *buffer_index)
+            .collect();
+        for buffer_index in buffer_indices {
+            self.check_buffer_file_on_disk(buffer_index, path);
+        }
+    }
+
+    /// Checks the files of all buffers.
+    pub fn check_all_files_on_disk(&mut self) {
+        let paths: Vec<path::PathBuf> = self
+            .buffers
+            .values()
+            .filter_map(|buffer| buffer.uri().and_then(disk::file_path))
+            .collect();
+        for path in paths {
+            self.check_file_on_disk(&path);
+        }
+    }
+
+    fn check_buffer_file_on_disk(&mut self, buffer_index: usize, path: &path::Path) {
+        let (uri, auto_reload, disk_state) = {
+            let buffer = &self.buffers[&buffer_index];
+            (
+                buffer.uri().unwrap().to_string(),
+                buffer.auto_reload && !buffer.is_dirty(),
+                buffer.disk_state.clone(),
+            )
+        };
+
+        let (content, new_disk_state) = match fs::metadata(path) {
+            Ok(metadata) => {
+                if disk_state
+                    .as_ref()
+                    .is_some_and(|state| state.same_metadata(&metadata))
+                {
+                    return;
+                }
+                match disk::read(path) {
+                    Ok((content, new_disk_state)) => (Some(content), Some(new_disk_state)),
+                    // We will get another chance once the file changes again.
+                    Err(_) => return,
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => (None, None),
+            Err(_) => return,
+        };
+
+        let content_changed = match (&disk_state, &new_disk_state) {
+            (Some(old), Some(new)) => old.hash != new.hash || old.size != new.size,
+            (None, None) => false,
+            _ => true,
+        };
+        self.buffers.get_mut(&buffer_index).unwrap().disk_state = new_disk_state.clone();
+        if !content_changed {
+            return;
+        }
+        let deleted = new_disk_state.is_none();
+
+        let mut reloaded = false;
+        if let (true, Some(content), Some(new_disk_state)) = (auto_reload, content, new_disk_state)
+        {
+            if let Ok((content, format)) = disk::decode(&content) {
+                let len_bytes = self.buffers[&buffer_index].len_bytes();
+                if self
+                    .replace(
+                        buffer_index,
+                        Position::Byte(0),
+                        Position::Byte(len_bytes),
+                        &content,
+                    )
+                    .is_ok()
+                {
+                    let buffer = self.buffers.get_mut(&buffer_index).unwrap();
+                    buffer.set_format(format);
+                    buffer.mark_saved(uri.clone(), new_disk_state);
+                    reloaded = true;
+                }
+            }
+        }
+
+        self.publish(
+            "on.buffer.file_changed_on_disk",
+            &BufferFileChangedOnDisk {
+                buffer_index,
+                uri,
+                deleted,
+                reloaded,
+            },
+        );
+    }
+
+    // Every subscriber gets the event, we do not wait for any of them.
+    fn publish<T: Serialize>(&mut self, function: &str, args: &T) {
+        let _ = self.client.broadcast(function, args);
+    }
+
+    fn publish_changes(&mut self, buffer_index: usize, changes: Vec<Change>) {
+        for change in changes {
+            self.publish(
+                "on.buffer.changed",
+                &BufferChanged {
+                    buffer_index,
+                    revision: change.revision,
+                    start: change.start,
+                    end: change.end,
+                    text: change.text,
+                },
+            );
+        }
+    }
 }
 
 impl ops::Deref for BuffersManager {
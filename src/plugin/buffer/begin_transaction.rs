@@ -0,0 +1,46 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use client;
+use plugin::buffer::base;
+use rpc;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::convert;
+use std::sync::{Arc, RwLock};
+use std::time;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub buffer_index: usize,
+    /// Milliseconds without any edit, undo, redo or transaction call on the buffer after which
+    /// the transaction is ended as if its client went away. Defaults to
+    /// 'DEFAULT_TRANSACTION_TIMEOUT'.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Response;
+
+pub struct Rpc {
+    pub buffers: Arc<RwLock<base::BuffersManager>>,
+}
+
+impl client::rpc::server::Rpc for Rpc {
+    fn call(&self, mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: Request = try_rpc!(context, serde_json::from_value(args));
+        let mut buffers = self.buffers.write().unwrap();
+
+        let buffer = try_rpc!(context, buffers.get_mut(request.buffer_index));
+        let timeout = request.timeout_ms.map_or(
+            base::DEFAULT_TRANSACTION_TIMEOUT,
+            time::Duration::from_millis,
+        );
+        buffer.begin_transaction(timeout);
+
+        let response = Response;
+        context.finish(rpc::Result::success(response)).unwrap();
+    }
+}
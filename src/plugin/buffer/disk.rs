@@ -0,0 +1,360 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use plugin::buffer::base;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, Read};
+use std::path;
+use std::time;
+
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+const UTF16_LE_BOM: &[u8] = &[0xff, 0xfe];
+const UTF16_BE_BOM: &[u8] = &[0xfe, 0xff];
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+/// How the content of a buffer is represented in its file. Buffers always use '\n' internally,
+/// conversion happens on reading and writing.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Format {
+    pub encoding: Encoding,
+    /// True if the file starts with a byte order mark.
+    pub bom: bool,
+    pub line_ending: LineEnding,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Format {
+    pub fn new() -> Self {
+        Format {
+            encoding: Encoding::Utf8,
+            bom: false,
+            line_ending: LineEnding::Lf,
+        }
+    }
+
+    /// The bytes the file starts with.
+    pub fn bom_bytes(&self) -> &'static [u8] {
+        match (self.bom, self.encoding) {
+            (false, _) | (true, Encoding::Latin1) => &[],
+            (true, Encoding::Utf8) => UTF8_BOM,
+            (true, Encoding::Utf16Le) => UTF16_LE_BOM,
+            (true, Encoding::Utf16Be) => UTF16_BE_BOM,
+        }
+    }
+
+    /// Appends 'text' converted into this format to 'out'. Fails if 'text' contains characters
+    /// that cannot be represented in this encoding.
+    pub fn encode(&self, text: &str, out: &mut Vec<u8>) -> io::Result<()> {
+        let text = match self.line_ending {
+            LineEnding::Lf => text.into(),
+            LineEnding::CrLf => text.replace('\n', "\r\n"),
+        };
+        match self.encoding {
+            Encoding::Utf8 => out.extend_from_slice(text.as_bytes()),
+            Encoding::Utf16Le => {
+                for unit in text.encode_utf16() {
+                    out.push(unit as u8);
+                    out.push((unit >> 8) as u8);
+                }
+            }
+            Encoding::Utf16Be => {
+                for unit in text.encode_utf16() {
+                    out.push((unit >> 8) as u8);
+                    out.push(unit as u8);
+                }
+            }
+            Encoding::Latin1 => {
+                for c in text.chars() {
+                    if c as u32 > 0xff {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{:?} cannot be encoded as Latin-1", c),
+                        ));
+                    }
+                    out.push(c as u8);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn decode_utf16(data: &[u8], encoding: Encoding) -> io::Result<String> {
+    if !data.len().is_multiple_of(2) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "odd number of bytes in UTF-16",
+        ));
+    }
+    let units: Vec<u16> = data
+        .chunks(2)
+        .map(|pair| match encoding {
+            Encoding::Utf16Be => ((pair[0] as u16) << 8) | pair[1] as u16,
+            _ => ((pair[1] as u16) << 8) | pair[0] as u16,
+        })
+        .collect();
+    String::from_utf16(&units).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+// Guesses if 'data' is UTF-16 without a byte order mark: text that is mostly ASCII has a zero in
+// every other byte then, which is very rare for any other encoding.
+fn guess_utf16(data: &[u8]) -> Option<Encoding> {
+    if data.is_empty() || !data.len().is_multiple_of(2) {
+        return None;
+    }
+    let num_units = data.len() / 2;
+    let zeros_at = |offset: usize| {
+        data.iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|b| **b == 0)
+            .count()
+    };
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    if odd * 2 > num_units && even == 0 {
+        Some(Encoding::Utf16Le)
+    } else if even * 2 > num_units && odd == 0 {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+/// Counts the lines of a file while it is read in pieces. The encoding is guessed from the first
+/// piece the same way 'decode' does it, so that a '\n' is only counted if it decodes to one.
+#[derive(Default)]
+pub struct LineCounter {
+    encoding: Option<Encoding>,
+    // The first byte of a UTF-16 code unit that was split between two pieces.
+    pending: Option<u8>,
+    pub lines: usize,
+}
+
+impl LineCounter {
+    pub fn update(&mut self, data: &[u8]) {
+        let encoding = *self.encoding.get_or_insert_with(|| {
+            if data.starts_with(UTF16_LE_BOM) {
+                Encoding::Utf16Le
+            } else if data.starts_with(UTF16_BE_BOM) {
+                Encoding::Utf16Be
+            } else {
+                guess_utf16(&data[..data.len() & !1]).unwrap_or(Encoding::Utf8)
+            }
+        });
+        match encoding {
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut bytes = self.pending.take().into_iter().chain(data.iter().cloned());
+                while let Some(first) = bytes.next() {
+                    let second = match bytes.next() {
+                        Some(second) => second,
+                        None => {
+                            self.pending = Some(first);
+                            break;
+                        }
+                    };
+                    let unit = match encoding {
+                        Encoding::Utf16Be => ((first as u16) << 8) | second as u16,
+                        _ => ((second as u16) << 8) | first as u16,
+                    };
+                    if unit == b'\n' as u16 {
+                        self.lines += 1;
+                    }
+                }
+            }
+            // '\n' is never part of a longer UTF-8 sequence.
+            Encoding::Utf8 | Encoding::Latin1 => {
+                self.lines += data.iter().filter(|b| **b == b'\n').count()
+            }
+        }
+    }
+}
+
+/// Turns the raw content of a file into text for a buffer and figures out how it was stored.
+/// Files that are neither Unicode nor have a byte order mark are taken to be Latin-1, so this only
+/// fails for broken UTF-16.
+pub fn decode(data: &[u8]) -> io::Result<(String, Format)> {
+    let mut format = Format::new();
+    let mut text = if data.starts_with(UTF8_BOM) {
+        format.bom = true;
+        String::from_utf8(data[UTF8_BOM.len()..].to_vec())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+    } else if data.starts_with(UTF16_LE_BOM) {
+        format.bom = true;
+        format.encoding = Encoding::Utf16Le;
+        decode_utf16(&data[UTF16_LE_BOM.len()..], format.encoding)?
+    } else if data.starts_with(UTF16_BE_BOM) {
+        format.bom = true;
+        format.encoding = Encoding::Utf16Be;
+        decode_utf16(&data[UTF16_BE_BOM.len()..], format.encoding)?
+    } else {
+        let utf16 = guess_utf16(data).and_then(|encoding| {
+            decode_utf16(data, encoding)
+                .ok()
+                .map(|text| (text, encoding))
+        });
+        match utf16 {
+            Some((text, encoding)) => {
+                format.encoding = encoding;
+                text
+            }
+            None => match ::std::str::from_utf8(data) {
+                Ok(text) => text.into(),
+                Err(_) => {
+                    format.encoding = Encoding::Latin1;
+                    data.iter().map(|b| *b as char).collect()
+                }
+            },
+        }
+    };
+
+    // We only convert line endings if all of them agree. Otherwise we would change the file on
+    // saving.
+    let num_lf = text.matches('\n').count();
+    if num_lf > 0 && text.matches("\r\n").count() == num_lf {
+        format.line_ending = LineEnding::CrLf;
+        text = text.replace("\r\n", "\n");
+    }
+    Ok((text, format))
+}
+
+/// What we know about a file on disk, so that we can tell if somebody else modified it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DiskState {
+    pub modified: Option<time::SystemTime>,
+    pub size: u64,
+    pub hash: u64,
+}
+
+impl DiskState {
+    /// True if 'metadata' could describe the same file content as this state without looking at
+    /// the content.
+    pub fn same_metadata(&self, metadata: &fs::Metadata) -> bool {
+        self.size == metadata.len() && self.modified == metadata.modified().ok()
+    }
+}
+
+pub struct Hash {
+    hasher: DefaultHasher,
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        Hash {
+            hasher: DefaultHasher::new(),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.write(data);
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hasher.finish()
+    }
+}
+
+/// Reads the full file at 'path' and returns its raw content together with its state.
+pub fn read(path: &path::Path) -> io::Result<(Vec<u8>, DiskState)> {
+    let mut file = fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    let mut content = Vec::with_capacity(metadata.len() as usize);
+    file.read_to_end(&mut content)?;
+
+    let mut hash = Hash::new();
+    hash.update(&content);
+    let state = DiskState {
+        modified: metadata.modified().ok(),
+        size: content.len() as u64,
+        hash: hash.finish(),
+    };
+    Ok((content, state))
+}
+
+/// Like 'read', but reads the file in pieces of 'chunk_size' bytes and hands each one to
+/// 'progress' after it was read. If 'progress' returns false, reading stops with an error of kind
+/// 'Interrupted'.
+pub fn read_chunked<F>(
+    path: &path::Path,
+    chunk_size: usize,
+    mut progress: F,
+) -> io::Result<(Vec<u8>, DiskState)>
+where
+    F: FnMut(&[u8]) -> bool,
+{
+    let mut file = fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    let mut content = Vec::with_capacity(metadata.len() as usize);
+    let mut hash = Hash::new();
+    let mut chunk = vec![0; chunk_size];
+    loop {
+        let len = match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        content.extend_from_slice(&chunk[..len]);
+        hash.update(&chunk[..len]);
+        if !progress(&chunk[..len]) {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "reading was stopped",
+            ));
+        }
+    }
+
+    let state = DiskState {
+        modified: metadata.modified().ok(),
+        size: content.len() as u64,
+        hash: hash.finish(),
+    };
+    Ok((content, state))
+}
+
+/// Makes 'path' absolute and resolves all symlinks, so that every file has exactly one URI. The
+/// file itself does not need to exist, only the directory it would be in.
+pub fn canonical_path(path: &path::Path) -> io::Result<path::PathBuf> {
+    match fs::canonicalize(path) {
+        Ok(path) => Ok(path),
+        Err(err) => {
+            let file_name = path.file_name().ok_or(err)?;
+            let directory = match path.parent() {
+                Some(directory) if !directory.as_os_str().is_empty() => directory,
+                _ => path::Path::new("."),
+            };
+            Ok(fs::canonicalize(directory)?.join(file_name))
+        }
+    }
+}
+
+/// Returns the 'file://' URI for 'path'.
+pub fn file_uri(path: &path::Path) -> String {
+    format!("{}{}", base::FILE_PREFIX, path.to_string_lossy())
+}
+
+/// Returns the path for a 'file://' URI.
+pub fn file_path(uri: &str) -> Option<path::PathBuf> {
+    uri.strip_prefix(base::FILE_PREFIX).map(path::PathBuf::from)
+}
@@ -0,0 +1,36 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use client;
+use plugin::buffer::base;
+use rpc;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::convert;
+use std::sync::{Arc, RwLock};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub buffer_index: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Response;
+
+pub struct Rpc {
+    pub buffers: Arc<RwLock<base::BuffersManager>>,
+}
+
+impl client::rpc::server::Rpc for Rpc {
+    fn call(&self, mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: Request = try_rpc!(context, serde_json::from_value(args));
+        let mut buffers = self.buffers.write().unwrap();
+
+        let buffer = try_rpc!(context, buffers.get_mut(request.buffer_index));
+        try_rpc!(context, buffer.end_transaction());
+
+        let response = Response;
+        context.finish(rpc::Result::success(response)).unwrap();
+    }
+}
@@ -17,6 +17,7 @@ pub struct Request {
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct Response {
     pub content: String,
+    pub revision: u64,
 }
 
 pub struct Rpc {
@@ -32,6 +33,7 @@ impl client::rpc::server::Rpc for Rpc {
 
         let response = Response {
             content: buffer.to_string(),
+            revision: buffer.revision(),
         };
         context.finish(rpc::Result::success(response)).unwrap();
     }
@@ -0,0 +1,47 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use client;
+use plugin::buffer::base;
+use rpc;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::convert;
+use std::sync::{Arc, RwLock};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub buffer_index: usize,
+    pub first: usize,
+    pub count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Response {
+    /// The lines without their line endings. Less than requested if the buffer ends before.
+    pub lines: Vec<String>,
+    pub revision: u64,
+    pub len_lines: usize,
+}
+
+pub struct Rpc {
+    pub buffers: Arc<RwLock<base::BuffersManager>>,
+}
+
+impl client::rpc::server::Rpc for Rpc {
+    fn call(&self, mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: Request = try_rpc!(context, serde_json::from_value(args));
+        let buffers = self.buffers.read().unwrap();
+
+        let buffer = try_rpc!(context, buffers.get(request.buffer_index));
+        let lines = try_rpc!(context, buffer.lines(request.first, request.count));
+
+        let response = Response {
+            lines,
+            revision: buffer.revision(),
+            len_lines: buffer.len_lines(),
+        };
+        context.finish(rpc::Result::success(response)).unwrap();
+    }
+}
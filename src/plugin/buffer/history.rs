@@ -0,0 +1,125 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+/// One primitive edit of a buffer: 'removed' was replaced by 'inserted' at char index 'start'.
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub start: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    /// The edit that undoes this one.
+    pub fn inverse(&self) -> Edit {
+        Edit {
+            start: self.start,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+}
+
+struct Node {
+    parent: usize,
+    edits: Vec<Edit>,
+    // The child that was visited most recently. This is where redo() goes.
+    last_child: Option<usize>,
+}
+
+/// An undo tree. Every node but the root holds a group of edits that are undone and redone as one
+/// unit. Undoing and then editing does not throw away the undone edits, it starts a new branch.
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    transaction_depth: usize,
+    // True if the current node belongs to the running transaction and new edits should be
+    // appended to it.
+    transaction_has_node: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            nodes: vec![Node {
+                parent: 0,
+                edits: Vec::new(),
+                last_child: None,
+            }],
+            current: 0,
+            transaction_depth: 0,
+            transaction_has_node: false,
+        }
+    }
+
+    /// An identifier for the state the buffer is in right now.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction_depth > 0
+    }
+
+    /// Starts grouping all following edits till the matching 'end_transaction'. Transactions
+    /// nest, only the outermost one defines the group.
+    pub fn begin_transaction(&mut self) {
+        self.transaction_depth += 1;
+    }
+
+    /// Returns false if there was no transaction running.
+    pub fn end_transaction(&mut self) -> bool {
+        if self.transaction_depth == 0 {
+            return false;
+        }
+        self.transaction_depth -= 1;
+        if self.transaction_depth == 0 {
+            self.transaction_has_node = false;
+        }
+        true
+    }
+
+    /// Ends all running transactions at once. The edits they made so far stay one group.
+    pub fn abandon_transactions(&mut self) {
+        self.transaction_depth = 0;
+        self.transaction_has_node = false;
+    }
+
+    pub fn record(&mut self, edit: Edit) {
+        if self.transaction_has_node {
+            self.nodes[self.current].edits.push(edit);
+            return;
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            parent: self.current,
+            edits: vec![edit],
+            last_child: None,
+        });
+        self.nodes[self.current].last_child = Some(index);
+        self.current = index;
+        self.transaction_has_node = self.in_transaction();
+    }
+
+    /// Moves one step towards the root and returns the edits that need to be applied to get there
+    /// in order. Returns None if there is nothing to undo.
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        if self.current == 0 {
+            return None;
+        }
+        let node = &self.nodes[self.current];
+        let edits = node.edits.iter().rev().map(|edit| edit.inverse()).collect();
+        self.current = node.parent;
+        Some(edits)
+    }
+
+    /// Moves one step back towards the most recently visited leaf and returns the edits that need
+    /// to be applied to get there in order. Returns None if there is nothing to redo.
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        let child = self.nodes[self.current].last_child?;
+        self.current = child;
+        Some(self.nodes[child].edits.clone())
+    }
+}
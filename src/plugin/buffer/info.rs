@@ -0,0 +1,51 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use client;
+use plugin::buffer::base;
+use plugin::buffer::disk;
+use rpc;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::convert;
+use std::sync::{Arc, RwLock};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub buffer_index: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub uri: Option<String>,
+    pub revision: u64,
+    pub dirty: bool,
+    pub loading: bool,
+    pub len_bytes: usize,
+    pub len_lines: usize,
+    pub format: disk::Format,
+}
+
+pub struct Rpc {
+    pub buffers: Arc<RwLock<base::BuffersManager>>,
+}
+
+impl client::rpc::server::Rpc for Rpc {
+    fn call(&self, mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: Request = try_rpc!(context, serde_json::from_value(args));
+        let buffers = self.buffers.read().unwrap();
+
+        let buffer = try_rpc!(context, buffers.get(request.buffer_index));
+        let response = Response {
+            uri: buffer.uri().map(|uri| uri.to_string()),
+            revision: buffer.revision(),
+            dirty: buffer.is_dirty(),
+            loading: buffer.is_loading(),
+            len_bytes: buffer.len_bytes(),
+            len_lines: buffer.len_lines(),
+            format: buffer.format(),
+        };
+        context.finish(rpc::Result::success(response)).unwrap();
+    }
+}
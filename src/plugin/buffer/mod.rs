@@ -5,7 +5,7 @@
 use client;
 use error::Result;
 use plugin;
-use std::sync::{Arc, RwLock};
+use std::sync::{mpsc, Arc, RwLock};
 
 pub struct Plugin {
     _client: client::Client,
@@ -14,13 +14,31 @@ pub struct Plugin {
 
 impl Plugin {
     pub fn new(mut client: client::Client) -> Result<Self> {
-        let buffers = Arc::new(RwLock::new(base::BuffersManager::new(client.clone()?)));
+        let (file_events_tx, file_events_rx) = mpsc::channel();
+        let buffers = Arc::new(RwLock::new(base::BuffersManager::new(
+            client.clone()?,
+            file_events_tx,
+        )));
+        watcher::spawn(Arc::downgrade(&buffers), file_events_rx);
         let rpc_map = rpc_map! {
             "buffer.new" => new::Rpc { buffers: buffers.clone() },
             "buffer.delete" => delete::Rpc { buffers: buffers.clone() },
             "buffer.get_content" => get_content::Rpc { buffers: buffers.clone() },
+            "buffer.get_lines" => get_lines::Rpc { buffers: buffers.clone() },
+            "buffer.get_range" => get_range::Rpc { buffers: buffers.clone() },
             "buffer.open" => open::Rpc { buffers: buffers.clone() },
             "buffer.list" => list::Rpc { buffers: buffers.clone() },
+            "buffer.info" => info::Rpc { buffers: buffers.clone() },
+            "buffer.insert" => insert::Rpc { buffers: buffers.clone() },
+            "buffer.delete_range" => delete_range::Rpc { buffers: buffers.clone() },
+            "buffer.replace" => replace::Rpc { buffers: buffers.clone() },
+            "buffer.undo" => undo::Rpc { buffers: buffers.clone() },
+            "buffer.redo" => redo::Rpc { buffers: buffers.clone() },
+            "buffer.begin_transaction" => begin_transaction::Rpc { buffers: buffers.clone() },
+            "buffer.end_transaction" => end_transaction::Rpc { buffers: buffers.clone() },
+            "buffer.save" => save::Rpc { buffers: buffers.clone() },
+            "buffer.save_as" => save_as::Rpc { buffers: buffers.clone() },
+            "buffer.set_auto_reload" => set_auto_reload::Rpc { buffers: buffers.clone() },
         };
         plugin::register_rpc(&mut client, rpc_map)?;
         Ok(Plugin {
@@ -31,8 +49,29 @@ impl Plugin {
 }
 
 mod base;
+pub mod begin_transaction;
 pub mod delete;
+pub mod delete_range;
+mod disk;
+pub mod end_transaction;
 pub mod get_content;
+pub mod get_lines;
+pub mod get_range;
+mod history;
+pub mod info;
+pub mod insert;
 pub mod list;
 pub mod new;
 pub mod open;
+pub mod redo;
+pub mod replace;
+pub mod save;
+pub mod save_as;
+pub mod set_auto_reload;
+pub mod undo;
+mod watcher;
+
+pub use self::base::{
+    BufferChanged, BufferFileChangedOnDisk, BufferReloaded, BufferSaved, Location, Position,
+};
+pub use self::disk::{Encoding, Format, LineEnding};
@@ -13,6 +13,9 @@ use std::sync::{Arc, RwLock};
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct Request {
     pub content: Option<String>,
+    /// Where the content came from. Plugins that open URIs the buffer plugin does not know set
+    /// this.
+    pub uri: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -31,10 +34,13 @@ impl client::rpc::server::Rpc for Rpc {
         let request: Request = try_rpc!(context, serde_json::from_value(args));
         let mut buffers = self.buffers.write().unwrap();
 
-        let buffer = match request.content {
+        let mut buffer = match request.content {
             Some(content) => base::Buffer::from_string(content),
             None => base::Buffer::new(),
         };
+        if let Some(uri) = request.uri {
+            buffer.set_uri(uri);
+        }
 
         let response = Response {
             buffer_index: buffers.new_buffer(buffer),
@@ -4,14 +4,21 @@
 
 use client;
 use plugin::buffer::base;
+use plugin::buffer::disk;
 use rpc;
 use serde::{Deserialize, Serialize};
 use serde_json;
 use std::convert;
 use std::fs;
-use std::io::Read;
+use std::io::{self, Read};
 use std::path;
 use std::sync::{Arc, RwLock};
+use std::thread;
+
+/// Files at least this large are read in the background.
+pub const STREAMING_THRESHOLD: u64 = 1 << 20;
+
+const CHUNK_SIZE: usize = 1 << 16;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct Request {
@@ -23,30 +30,171 @@ pub struct Response {
     pub buffer_index: usize,
 }
 
+/// Sent as partial response while a large file is read. The first one comes right away, so the
+/// buffer can be shown before it is loaded. Cancelling the RPC stops loading and deletes the
+/// buffer.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub buffer_index: usize,
+    pub bytes_loaded: u64,
+    pub total_bytes: u64,
+    pub lines_indexed: usize,
+}
+
+/// Handles 'file://', 'untitled:', 'scratch:' and 'stdin:' URIs and returns NotHandled for
+/// everything else.
+///
+/// Plugins can support more schemes by registering their own 'buffer.open' with a priority below
+/// the default one, so they are asked first. They return NotHandled for URIs they do not
+/// understand, otherwise they fetch the content and create the buffer by calling 'buffer.new'
+/// with 'content' and 'uri' set. They answer with the 'buffer_index' they got back as a
+/// 'Response'. Saving works the same way: the plugin registers a 'buffer.save' of its own that is
+/// asked before the default one, which fails with 'no_uri' for buffers that are not files.
 pub struct Rpc {
     pub buffers: Arc<RwLock<base::BuffersManager>>,
 }
 
-impl client::rpc::server::Rpc for Rpc {
-    fn call(&self, mut context: client::rpc::server::Context, args: serde_json::Value) {
-        const FILE_PREFIX: &'static str = "file://";
-        let mut request: Request = try_rpc!(context, serde_json::from_value(args));
-        if !request.uri.starts_with(FILE_PREFIX) {
-            context.finish(rpc::Result::NotHandled).unwrap();
-            return;
+impl Rpc {
+    fn open_file(&self, uri: String) -> io::Result<usize> {
+        let path = path::Path::new(&uri[base::FILE_PREFIX.len()..]);
+        let (content, disk_state) = disk::read(path)?;
+        let (content, format) = disk::decode(&content)?;
+
+        let mut buffer = base::Buffer::from_string(content);
+        buffer.set_format(format);
+        buffer.mark_saved(uri, disk_state);
+
+        let mut buffers = self.buffers.write().unwrap();
+        Ok(buffers.new_buffer(buffer))
+    }
+
+    // The buffer is created empty and filled once the whole file was read, so that it never
+    // contains a partially decoded file.
+    fn open_file_streaming(
+        &self,
+        mut context: client::rpc::server::Context,
+        uri: String,
+        total_bytes: u64,
+    ) {
+        let path = disk::file_path(&uri).unwrap();
+        let buffer_index = self
+            .buffers
+            .write()
+            .unwrap()
+            .new_buffer(base::Buffer::loading(uri));
+        let buffers = self.buffers.clone();
+
+        thread::spawn(move || {
+            let mut progress = Progress {
+                buffer_index,
+                bytes_loaded: 0,
+                total_bytes,
+                lines_indexed: 0,
+            };
+            let mut line_counter = disk::LineCounter::default();
+            let loaded = if context.update(&progress).is_ok() {
+                disk::read_chunked(&path, CHUNK_SIZE, |chunk| {
+                    progress.bytes_loaded += chunk.len() as u64;
+                    line_counter.update(chunk);
+                    progress.lines_indexed = line_counter.lines;
+                    context.update(&progress).is_ok()
+                })
+                .and_then(|(content, disk_state)| {
+                    let (content, format) = disk::decode(&content)?;
+                    Ok((content, format, disk_state))
+                })
+            } else {
+                Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"))
+            };
+
+            let mut buffers = buffers.write().unwrap();
+            let result = match loaded {
+                Ok(_) if context.cancelled() => {
+                    let _ = buffers.delete_buffer(buffer_index);
+                    return;
+                }
+                Ok((content, format, disk_state)) => buffers
+                    .finish_loading(buffer_index, content, format, disk_state)
+                    .map(|_| rpc::Result::success(Response { buffer_index }))
+                    .unwrap_or_else(|err| rpc::Result::Err(err.into())),
+                Err(err) => {
+                    let _ = buffers.delete_buffer(buffer_index);
+                    rpc::Result::Err(err.into())
+                }
+            };
+            // This fails if we were cancelled, which is fine.
+            let _ = context.finish(result);
+        });
+    }
+
+    // Untitled and scratch buffers only live in memory, so opening the same URI twice gives the
+    // same buffer.
+    fn open_in_memory(&self, prefix: &str, uri: String) -> usize {
+        let mut buffers = self.buffers.write().unwrap();
+        let uri = if uri == prefix {
+            buffers.unused_uri(prefix)
+        } else {
+            uri
+        };
+        if let Some(buffer_index) = buffers.find_by_uri(&uri) {
+            return buffer_index;
         }
-        request.uri.drain(..FILE_PREFIX.len());
 
-        let mut file = try_rpc!(context, fs::File::open(path::Path::new(&request.uri)));
-        let mut content = String::new();
-        try_rpc!(context, file.read_to_string(&mut content));
+        let mut buffer = base::Buffer::new();
+        buffer.set_uri(uri);
+        buffers.new_buffer(buffer)
+    }
+
+    // Stdin can only be read once, so we hand out the buffer we already have if possible. Reading
+    // can block for a long time, so we do not hold the lock meanwhile.
+    fn open_stdin(&self) -> io::Result<usize> {
+        if let Some(buffer_index) = self.buffers.read().unwrap().find_by_uri(base::STDIN_URI) {
+            return Ok(buffer_index);
+        }
 
-        let buffer = base::Buffer::from_string(content);
+        let mut content = Vec::new();
+        io::stdin().read_to_end(&mut content)?;
+        let (content, format) = disk::decode(&content)?;
 
         let mut buffers = self.buffers.write().unwrap();
-        let response = Response {
-            buffer_index: buffers.new_buffer(buffer),
+        // Somebody else might have been quicker and got the content.
+        if let Some(buffer_index) = buffers.find_by_uri(base::STDIN_URI) {
+            return Ok(buffer_index);
+        }
+        let mut buffer = base::Buffer::from_string(content);
+        buffer.set_format(format);
+        buffer.set_uri(base::STDIN_URI.into());
+        Ok(buffers.new_buffer(buffer))
+    }
+}
+
+impl client::rpc::server::Rpc for Rpc {
+    fn call(&self, mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: Request = try_rpc!(context, serde_json::from_value(args));
+        let buffer_index = if request.uri.starts_with(base::FILE_PREFIX) {
+            let path = try_rpc!(
+                context,
+                disk::canonical_path(&disk::file_path(&request.uri).unwrap())
+            );
+            let uri = disk::file_uri(&path);
+            let total_bytes = try_rpc!(context, fs::metadata(path)).len();
+            if total_bytes >= STREAMING_THRESHOLD {
+                self.open_file_streaming(context, uri, total_bytes);
+                return;
+            }
+            try_rpc!(context, self.open_file(uri))
+        } else if request.uri.starts_with(base::UNTITLED_PREFIX) {
+            self.open_in_memory(base::UNTITLED_PREFIX, request.uri)
+        } else if request.uri.starts_with(base::SCRATCH_PREFIX) {
+            self.open_in_memory(base::SCRATCH_PREFIX, request.uri)
+        } else if request.uri == base::STDIN_URI {
+            try_rpc!(context, self.open_stdin())
+        } else {
+            context.finish(rpc::Result::NotHandled).unwrap();
+            return;
         };
+
+        let response = Response { buffer_index };
         context.finish(rpc::Result::success(response)).unwrap();
     }
 }
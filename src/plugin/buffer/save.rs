@@ -0,0 +1,46 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use client;
+use plugin::buffer::base;
+use rpc;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::convert;
+use std::sync::{Arc, RwLock};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub buffer_index: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub revision: u64,
+}
+
+pub struct Rpc {
+    pub buffers: Arc<RwLock<base::BuffersManager>>,
+}
+
+impl client::rpc::server::Rpc for Rpc {
+    fn call(&self, mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: Request = try_rpc!(context, serde_json::from_value(args));
+        let mut buffers = self.buffers.write().unwrap();
+
+        // Buffers that are not backed by a file, like 'untitled:' ones, need 'buffer.save_as'.
+        let uri = {
+            let buffer = try_rpc!(context, buffers.get(request.buffer_index));
+            match buffer.uri() {
+                Some(uri) if uri.starts_with(base::FILE_PREFIX) => uri.to_string(),
+                _ => try_rpc!(context, Err(base::BufferError::NoUri)),
+            }
+        };
+
+        let response = Response {
+            revision: try_rpc!(context, buffers.save(request.buffer_index, uri)),
+        };
+        context.finish(rpc::Result::success(response)).unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use client;
+use plugin::buffer::base;
+use rpc;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::convert;
+use std::sync::{Arc, RwLock};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub buffer_index: usize,
+    pub uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub revision: u64,
+}
+
+pub struct Rpc {
+    pub buffers: Arc<RwLock<base::BuffersManager>>,
+}
+
+impl client::rpc::server::Rpc for Rpc {
+    fn call(&self, mut context: client::rpc::server::Context, args: serde_json::Value) {
+        let request: Request = try_rpc!(context, serde_json::from_value(args));
+        if !request.uri.starts_with(base::FILE_PREFIX) {
+            context.finish(rpc::Result::NotHandled).unwrap();
+            return;
+        }
+
+        let mut buffers = self.buffers.write().unwrap();
+        let response = Response {
+            revision: try_rpc!(context, buffers.save(request.buffer_index, request.uri)),
+        };
+        context.finish(rpc::Result::success(response)).unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use notify::DebouncedEvent;
+use plugin::buffer::base;
+use std::sync::{mpsc, RwLock, Weak};
+use std::thread;
+
+/// Spawns a thread that checks the buffers whenever a watched file changes on disk. The thread
+/// ends once the 'BuffersManager' and with it the sending side of 'events' is gone.
+pub fn spawn(
+    buffers: Weak<RwLock<base::BuffersManager>>,
+    events: mpsc::Receiver<DebouncedEvent>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        while let Ok(event) = events.recv() {
+            let buffers = match buffers.upgrade() {
+                Some(buffers) => buffers,
+                None => break,
+            };
+            let mut buffers = buffers.write().unwrap();
+            match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Chmod(path)
+                | DebouncedEvent::Remove(path) => buffers.check_file_on_disk(&path),
+                DebouncedEvent::Rename(from, to) => {
+                    buffers.check_file_on_disk(&from);
+                    buffers.check_file_on_disk(&to);
+                }
+                DebouncedEvent::Rescan => buffers.check_all_files_on_disk(),
+                DebouncedEvent::NoticeWrite(_)
+                | DebouncedEvent::NoticeRemove(_)
+                | DebouncedEvent::Error(..) => (),
+            }
+        }
+    })
+}
@@ -67,13 +67,19 @@ impl client::rpc::server::Rpc for ListFiles {
     fn call(&self, mut context: client::rpc::server::Context, args: serde_json::Value) {
         let request: ListFilesRequest = try_rpc!(context, serde_json::from_value(args));
         // NOCOM handle the result
-        let _ = self.client.write().unwrap().call(
-            "log.debug",
-            &plugin::log::debug::Request {
-                message: String::from("list files called"),
-                time: plugin::log::current(),
-            },
-        );
+        // We need to wait, dropping the call would cancel it.
+        let _ = self
+            .client
+            .write()
+            .unwrap()
+            .call(
+                "log.debug",
+                &plugin::log::debug::Request {
+                    message: String::from("list files called"),
+                    time: plugin::log::current(),
+                },
+            )
+            .and_then(|mut rpc| rpc.wait());
 
         thread::spawn(move || {
             let mut files = Vec::new();
@@ -32,6 +32,9 @@ pub enum ErrorKind {
     UnknownRpc,
     Io,
     InvalidArgs,
+    Timeout,
+    CalleeDisconnected,
+    Cancelled,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -93,6 +96,23 @@ pub struct Call {
     pub function: String,
     pub context: String,
     pub args: serde_json::Value,
+    /// Milliseconds the RPC has to finish, counted from when the server receives the call. If it
+    /// takes longer, it is cancelled and the caller gets an error of kind 'Timeout'.
+    #[serde(default)]
+    pub deadline_ms: Option<u64>,
+    /// The context of the RPC this call was made on behalf of. Cancelling that RPC also cancels
+    /// this one and its caller gets an error of kind 'Cancelled'.
+    #[serde(default)]
+    pub parent: Option<String>,
+}
+
+/// A call that goes to every handler of 'function' at once. Nobody waits for the handlers, so
+/// everything they answer is dropped.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Broadcast {
+    pub function: String,
+    pub context: String,
+    pub args: serde_json::Value,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -2,17 +2,19 @@
 // Licensed under the Apache License, Version 2.0. See LICENSE.txt
 // in the project root for license information.
 
+use serde_json;
 use server::ipc_bridge;
+use std::collections::hash_map;
 use std::collections::HashMap;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, PartialEq)]
 pub struct ApiInfo {
     pub client_id: ipc_bridge::ClientId,
     pub priority: u16,
+    pub schema: Option<serde_json::Value>,
 }
 
 pub struct ApiTable {
-    // NOCOM should use a better datastruct instead of Vec for get_next()
     name_infos: HashMap<String, Vec<ApiInfo>>,
 }
 
@@ -45,6 +47,22 @@ impl ApiTable {
         }
     }
 
+    /// Removes the handler 'client_id' registered for 'name'. Returns false if there was none.
+    pub fn deregister(&mut self, name: &String, client_id: &ipc_bridge::ClientId) -> bool {
+        let (removed, is_empty) = match self.name_infos.get_mut(name) {
+            Some(infos) => {
+                let len = infos.len();
+                infos.retain(|info| info.client_id != *client_id);
+                (infos.len() != len, infos.is_empty())
+            }
+            None => return false,
+        };
+        if is_empty {
+            self.name_infos.remove(name);
+        }
+        removed
+    }
+
     pub fn get_first(&self, name: &String) -> Option<&ApiInfo> {
         match self.name_infos.get(name) {
             Some(infos) => infos.first(),
@@ -52,13 +70,21 @@ impl ApiTable {
         }
     }
 
-    pub fn get_next(&self, name: &String, client_id: &ipc_bridge::ClientId) -> Option<&ApiInfo> {
+    pub fn get_all(&self, name: &String) -> &[ApiInfo] {
         match self.name_infos.get(name) {
-            Some(infos) => infos
-                .iter()
-                .skip_while(|info| info.client_id != *client_id)
-                .nth(1),
-            None => None,
+            Some(infos) => infos,
+            None => &[],
         }
     }
+
+    /// All registered names with their handlers, in the order they are tried.
+    pub fn iter(&self) -> hash_map::Iter<'_, String, Vec<ApiInfo>> {
+        self.name_infos.iter()
+    }
+
+    pub fn is_registered(&self, name: &String, client_id: &ipc_bridge::ClientId) -> bool {
+        self.get_all(name)
+            .iter()
+            .any(|info| info.client_id == *client_id)
+    }
 }
@@ -0,0 +1,111 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use server::swiboe;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+enum Message {
+    Watch(time::Instant, String),
+    Forget(String),
+}
+
+/// Tells the server once the deadlines of RPCs passed. A single thread waits for all of them. It
+/// ends when this is dropped.
+pub struct Deadlines {
+    messages: mpsc::Sender<Message>,
+}
+
+impl Deadlines {
+    pub fn spawn(commands: swiboe::SenderTo) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || run(rx, commands));
+        Deadlines { messages: tx }
+    }
+
+    /// Sends 'RpcTimeout' for 'context' after 'timeout'.
+    pub fn watch(&self, context: String, timeout: time::Duration) {
+        let deadline = time::Instant::now() + timeout;
+        // Cannot fail, the thread only ends once we are dropped.
+        let _ = self.messages.send(Message::Watch(deadline, context));
+    }
+
+    /// Stops watching 'context', because its RPC finished or was cancelled.
+    pub fn forget(&self, context: String) {
+        let _ = self.messages.send(Message::Forget(context));
+    }
+}
+
+// The deadlines that did not pass yet, ordered by when they pass. The sequence number keeps
+// entries for the same instant apart.
+#[derive(Default)]
+struct Pending {
+    by_deadline: BTreeMap<(time::Instant, u64), String>,
+    by_context: HashMap<String, (time::Instant, u64)>,
+    next_seq: u64,
+}
+
+impl Pending {
+    fn watch(&mut self, deadline: time::Instant, context: String) {
+        self.forget(&context);
+        let key = (deadline, self.next_seq);
+        self.next_seq += 1;
+        self.by_context.insert(context.clone(), key);
+        self.by_deadline.insert(key, context);
+    }
+
+    fn forget(&mut self, context: &str) {
+        if let Some(key) = self.by_context.remove(context) {
+            self.by_deadline.remove(&key);
+        }
+    }
+
+    fn earliest(&self) -> Option<time::Instant> {
+        self.by_deadline
+            .keys()
+            .next()
+            .map(|&(deadline, _)| deadline)
+    }
+
+    fn pop(&mut self) -> Option<String> {
+        let key = *self.by_deadline.keys().next()?;
+        let context = self.by_deadline.remove(&key).unwrap();
+        self.by_context.remove(&context);
+        Some(context)
+    }
+}
+
+fn run(messages: mpsc::Receiver<Message>, commands: swiboe::SenderTo) {
+    let mut pending = Pending::default();
+    loop {
+        // Waits for new messages until the earliest deadline passed.
+        let received = match pending.earliest() {
+            None => messages
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            Some(deadline) => {
+                let now = time::Instant::now();
+                if deadline <= now {
+                    Err(mpsc::RecvTimeoutError::Timeout)
+                } else {
+                    messages.recv_timeout(deadline - now)
+                }
+            }
+        };
+        match received {
+            Ok(Message::Watch(deadline, context)) => pending.watch(deadline, context),
+            Ok(Message::Forget(context)) => pending.forget(&context),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let context = pending.pop().unwrap();
+                // The RPC might have finished while we were waiting, then the server ignores this.
+                if commands.send(swiboe::Command::RpcTimeout(context)).is_err() {
+                    return;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
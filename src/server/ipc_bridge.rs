@@ -6,13 +6,18 @@ use ipc;
 use mio;
 use mio::tcp::{TcpListener, TcpStream};
 use mio::unix::{UnixListener, UnixStream};
+use rpc;
+use rustls;
+use serde_json;
 use server::swiboe;
+use server::Config;
 use std::io;
 use std::net;
 use std::path::Path;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use threadpool::ThreadPool;
+use tls::{self, TlsStream};
 use {Error, Result};
 
 // Number of threads to use for handling IO.
@@ -24,12 +29,167 @@ pub struct ClientId {
     pub token: mio::Token,
 }
 
+// Compares in constant time, so that the time until we answer does not tell how much of a guessed
+// token was right.
+fn token_matches(expected: Option<&str>, given: Option<&str>) -> bool {
+    let expected = match expected {
+        Some(expected) => expected.as_bytes(),
+        None => return true,
+    };
+    let given = given.unwrap_or("").as_bytes();
+    if expected.len() != given.len() {
+        return false;
+    }
+    expected
+        .iter()
+        .zip(given)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+// Answers the 'Hello' of a client and switches to the encoding it wants. Only clients that talk
+// our protocol version and know the 'auth_token' of the connection are let in. Returns true if
+// the client was let in. A client with the wrong version can try again, one with the wrong token
+// gets 'Error::Refused' and needs to be disconnected, so that it cannot guess on. Clients that
+// present the 'plugin_token' are the plugins of the server itself, their frames are not limited.
+fn on_hello<T: io::Read + io::Write>(
+    reader: &mut ipc::Reader<T>,
+    writer: &Mutex<ipc::Writer<T>>,
+    auth_token: Option<&str>,
+    plugin_token: &str,
+    hello: &ipc::Hello,
+) -> Result<()> {
+    let mut writer = writer.lock().unwrap();
+    let refusal = if hello.version != ipc::PROTOCOL_VERSION {
+        Some(format!(
+            "unsupported protocol version {}, expected {}",
+            hello.version,
+            ipc::PROTOCOL_VERSION
+        ))
+    } else if !token_matches(auth_token, hello.auth_token.as_deref()) {
+        Some("missing or invalid authentication token".to_string())
+    } else {
+        None
+    };
+    if let Some(reason) = refusal {
+        writer.queue_message(&ipc::Message::Refused(ipc::Refused {
+            version: ipc::PROTOCOL_VERSION,
+            reason: reason.clone(),
+        }));
+        return Err(Error::Refused(reason));
+    }
+
+    // We use what the client likes best, we support all encodings.
+    let encoding = hello
+        .encodings
+        .first()
+        .cloned()
+        .unwrap_or(ipc::Encoding::Json);
+    writer.queue_message(&ipc::Message::Welcome(ipc::Welcome {
+        version: ipc::PROTOCOL_VERSION,
+        features: ipc::features(),
+        encoding: encoding,
+    }));
+    writer.set_encoding(encoding);
+    reader.set_encoding(encoding);
+    if token_matches(Some(plugin_token), hello.auth_token.as_deref()) {
+        reader.set_max_frame_size(usize::MAX);
+    }
+    Ok(())
+}
+
+// Handles what a client sends before we let it in. Nothing of it reaches the server, calls are
+// answered with an error. Returns the 'Hello' if the client is let in now and an error if it
+// needs to be disconnected.
+fn on_message_before_welcome<T: io::Read + io::Write>(
+    reader: &mut ipc::Reader<T>,
+    writer: &Mutex<ipc::Writer<T>>,
+    auth_token: Option<&str>,
+    plugin_token: &str,
+    message: ipc::Message,
+) -> Result<Option<ipc::Hello>> {
+    match message {
+        ipc::Message::Hello(hello) => {
+            on_hello(reader, writer, auth_token, plugin_token, &hello)?;
+            return Ok(Some(hello));
+        }
+        ipc::Message::RpcCall(rpc_call) => {
+            writer
+                .lock()
+                .unwrap()
+                .queue_message(&ipc::Message::RpcResponse(rpc::Response {
+                    context: rpc_call.context,
+                    kind: rpc::ResponseKind::Last(rpc::Result::Err(rpc::Error {
+                        kind: rpc::ErrorKind::Io,
+                        details: Some(serde_json::to_value("hello_required").unwrap()),
+                    })),
+                }));
+        }
+        _ => (),
+    }
+    Ok(None)
+}
+
+// Passes what a welcomed client sends on to the server.
+fn forward_message(commands: &swiboe::SenderTo, client_id: ClientId, message: ipc::Message) {
+    // println!("{:?} -> Server: {:#?}", client_id, message);
+    match message {
+        // NOCOM(#sirver): pack them together in one message?
+        // NOCOM(#hrapp): that can actually also fail since the thread_pool does not wait for its
+        // thread to terminate on deletion.
+        ipc::Message::RpcCall(rpc_call) => {
+            commands
+                .send(swiboe::Command::RpcCall(client_id, rpc_call))
+                .expect("RpcCall");
+        }
+        ipc::Message::RpcResponse(rpc_response) => {
+            commands
+                .send(swiboe::Command::RpcResponse(rpc_response))
+                .expect("RpcResponse");
+        }
+        ipc::Message::RpcCancel(rpc_cancel) => {
+            commands
+                .send(swiboe::Command::RpcCancel(client_id, rpc_cancel))
+                .expect("RpcCancel");
+        }
+        ipc::Message::RpcBroadcast(rpc_broadcast) => {
+            commands
+                .send(swiboe::Command::RpcBroadcast(rpc_broadcast))
+                .expect("RpcBroadcast");
+        }
+        ipc::Message::Hello(_) | ipc::Message::Welcome(_) | ipc::Message::Refused(_) => {
+            // Clients say hello only once and only the server sends the others.
+        }
+    }
+}
+
+// What the server knows about a client. The reading thread and the ipc_bridge only change it
+// while holding its lock, and they tell the server while still holding it. So the server always
+// hears of a client before it gets anything the client sent and hears that it is gone after
+// the last message it got from it.
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum ClientState {
+    // The client did not yet say a 'Hello' that we accepted. The server does not know it.
+    Greeting,
+    // The server knows the client and gets what it sends.
+    Welcomed,
+    // We refused the client for good. It is disconnected once it got our answer.
+    Refused,
+    // The connection was removed. Nothing of the client reaches the server anymore.
+    Gone,
+}
+
 // We abstract over unix and TCP connections. Since receiver and sender both get a copy of the
 // socket, we need to clone them. Since we store them in slab (which means the trait cannot be
 // sized), we have to return boxes too.
 #[doc(hidden)]
 pub trait MioStream: Send + io::Read + io::Write + mio::Evented {
     fn try_clone(&self) -> io::Result<Box<dyn MioStream>>;
+
+    // True if the stream holds data for the client that waits for the socket to become writable.
+    fn wants_write(&self) -> bool {
+        false
+    }
 }
 
 impl MioStream for UnixStream {
@@ -44,11 +204,51 @@ impl MioStream for TcpStream {
     }
 }
 
+impl MioStream for TlsStream<TcpStream> {
+    fn try_clone(&self) -> io::Result<Box<dyn MioStream>> {
+        let socket = self.socket().try_clone()?;
+        Ok(Box::new(self.share(socket)))
+    }
+
+    fn wants_write(&self) -> bool {
+        TlsStream::wants_write(self)
+    }
+}
+
+impl mio::Evented for TlsStream<TcpStream> {
+    fn register(
+        &self,
+        selector: &mut mio::Selector,
+        token: mio::Token,
+        interest: mio::EventSet,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        self.socket().register(selector, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        selector: &mut mio::Selector,
+        token: mio::Token,
+        interest: mio::EventSet,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        self.socket().reregister(selector, token, interest, opts)
+    }
+
+    fn deregister(&self, selector: &mut mio::Selector) -> io::Result<()> {
+        self.socket().deregister(selector)
+    }
+}
+
 struct Connection<T: io::Read + io::Write> {
     // NOCOM(#sirver): messy design
     reader: Option<ipc::Reader<T>>,
     writer: Arc<Mutex<ipc::Writer<T>>>,
     client_id: ClientId,
+    // The token the client needs to present in its 'Hello', if any.
+    auth_token: Option<String>,
+    state: Arc<Mutex<ClientState>>,
 }
 
 pub struct IpcBridge {
@@ -58,6 +258,14 @@ pub struct IpcBridge {
     commands: swiboe::SenderTo,
     first_client_token: usize,
     next_serial: u64,
+    // Only applies to clients that are not our own plugins.
+    max_frame_size: usize,
+    // Clients connecting over TCP need to know this.
+    auth_token: Option<String>,
+    // Our own plugins present this, see 'on_hello'.
+    plugin_token: String,
+    // If set, TCP connections are encrypted.
+    tls: Option<Arc<rustls::ServerConfig>>,
     thread_pool: ThreadPool,
 }
 
@@ -68,6 +276,8 @@ impl IpcBridge {
         event_loop: &mut mio::EventLoop<Self>,
         socket_name: &Path,
         tcp_addresses: &Vec<String>,
+        config: Config,
+        plugin_token: String,
         server_commands: swiboe::SenderTo,
     ) -> Self {
         let unix_listener = UnixListener::bind(socket_name).unwrap();
@@ -106,6 +316,10 @@ impl IpcBridge {
             connections: mio::util::Slab::new_starting_at(mio::Token(first_client_token), 1024),
             commands: server_commands,
             next_serial: 1,
+            max_frame_size: config.max_frame_size,
+            auth_token: config.auth_token,
+            plugin_token: plugin_token,
+            tls: config.tls,
             thread_pool: ThreadPool::new(NUM_THREADS),
         }
     }
@@ -114,25 +328,27 @@ impl IpcBridge {
         &mut self,
         event_loop: &mut mio::EventLoop<Self>,
         stream: Box<T>,
+        auth_token: Option<String>,
     ) {
         // NOCOM(#sirver): can this be done in Some(token)?
         let serial = self.next_serial;
-        let commands = self.commands.clone();
+        let max_frame_size = self.max_frame_size;
         self.next_serial += 1;
         match self.connections.insert_with(|token| {
             let client_id = ClientId {
                 serial: serial,
                 token: token,
             };
-            let connection = Connection {
-                writer: Arc::new(Mutex::new(ipc::Writer::new(stream.try_clone().unwrap()))),
-                reader: Some(ipc::Reader::new(stream)),
+            let writer = ipc::Writer::new(stream.try_clone().unwrap());
+            let mut reader = ipc::Reader::new(stream as Box<dyn MioStream>);
+            reader.set_max_frame_size(max_frame_size);
+            Connection {
+                writer: Arc::new(Mutex::new(writer)),
+                reader: Some(reader),
                 client_id: client_id,
-            };
-            commands
-                .send(swiboe::Command::ClientConnected(client_id))
-                .expect("ClientConnected");
-            connection
+                auth_token: auth_token,
+                state: Arc::new(Mutex::new(ClientState::Greeting)),
+            }
         }) {
             Some(token) => {
                 // If we successfully insert, then register our connection.
@@ -181,13 +397,27 @@ impl IpcBridge {
         }
         Ok(())
     }
+
+    fn remove_connection(&mut self, token: mio::Token) {
+        if let Some(connection) = self.connections.remove(token) {
+            let mut state = connection.state.lock().unwrap();
+            if *state == ClientState::Welcomed {
+                self.commands
+                    .send(swiboe::Command::ClientDisconnected(connection.client_id))
+                    .expect("ClientDisconnected");
+            }
+            *state = ClientState::Gone;
+        }
+    }
 }
 
 pub enum Command {
     Quit,
     SendData(ClientId, ipc::Message),
-    ReRegisterForReading(mio::Token, ipc::Reader<Box<dyn MioStream>>),
+    ReRegisterForReading(ClientId, ipc::Reader<Box<dyn MioStream>>),
     ReRegisterForWriting(mio::Token),
+    // Drops the connection, for example because the client sent something we cannot read.
+    Disconnect(ClientId),
 }
 
 impl mio::Handler for IpcBridge {
@@ -223,13 +453,18 @@ impl mio::Handler for IpcBridge {
                     }
                 };
             }
-            Command::ReRegisterForReading(token, reader) => {
-                if let Some(conn) = self.connections.get_mut(token) {
+            Command::ReRegisterForReading(client_id, reader) => {
+                // The connection might have been dropped while we were reading and its token
+                // given to a new client. The reader is dropped then.
+                if let Some(conn) = self.connections.get_mut(client_id.token) {
+                    if conn.client_id != client_id {
+                        return;
+                    }
                     conn.reader = Some(reader);
                     event_loop
                         .reregister(
                             &*conn.reader.as_ref().unwrap().socket,
-                            token,
+                            client_id.token,
                             mio::EventSet::readable(),
                             mio::PollOpt::level() | mio::PollOpt::oneshot(),
                         )
@@ -240,6 +475,15 @@ impl mio::Handler for IpcBridge {
                 self.reregister_for_writing(token, event_loop)
                     .expect("reregister_for_writing");
             }
+            Command::Disconnect(client_id) => {
+                let is_connected = self
+                    .connections
+                    .get(client_id.token)
+                    .map_or(false, |conn| conn.client_id == client_id);
+                if is_connected {
+                    self.remove_connection(client_id.token);
+                }
+            }
         }
     }
 
@@ -253,7 +497,7 @@ impl mio::Handler for IpcBridge {
             UNIX_LISTENER => {
                 // Unix domain socket connection.
                 if let Some(stream) = self.unix_listener.accept().expect("UNIX_LISTENER::accept") {
-                    self.new_client(event_loop, Box::new(stream));
+                    self.new_client(event_loop, Box::new(stream), None);
                 }
             }
             mio::Token(some_token) if some_token < self.first_client_token => {
@@ -262,7 +506,17 @@ impl mio::Handler for IpcBridge {
                     .accept()
                     .expect("TCP listener::accept")
                 {
-                    self.new_client(event_loop, Box::new(stream));
+                    let auth_token = self.auth_token.clone();
+                    match self.tls.clone() {
+                        None => self.new_client(event_loop, Box::new(stream), auth_token),
+                        Some(tls) => {
+                            // This only fails for broken configs. We drop the connection then.
+                            if let Ok(session) = tls::server_session(tls) {
+                                let stream = Box::new(TlsStream::new(stream, session));
+                                self.new_client(event_loop, stream, auth_token);
+                            }
+                        }
+                    }
                 }
             }
             client_token => {
@@ -270,50 +524,77 @@ impl mio::Handler for IpcBridge {
                 if events.is_readable() {
                     if let Some(conn) = self.connections.get_mut(token) {
                         let mut reader = conn.reader.take().unwrap();
+                        let writer = conn.writer.clone();
                         let commands = self.commands.clone();
                         let client_id = conn.client_id;
+                        let auth_token = conn.auth_token.clone();
+                        let plugin_token = self.plugin_token.clone();
+                        let state = conn.state.clone();
                         let event_loop_sender = event_loop.channel();
                         self.thread_pool.execute(move || {
                             loop {
                                 match reader.try_read_message() {
-                                    // NOCOM(#sirver): should disconnect instead of panic.
-                                    Err(err) => panic!("Error while reading: {}", err),
+                                    Err(_) => {
+                                        // We cannot find the start of the next message after
+                                        // garbage, so the client has to go. Its reader is dropped
+                                        // here instead of being registered again.
+                                        let _ =
+                                            event_loop_sender.send(Command::Disconnect(client_id));
+                                        return;
+                                    }
                                     Ok(None) => break,
                                     Ok(Some(message)) => {
-                                        // println!("{:?} -> Server: {:#?}", client_id, message);
-                                        match message {
-                                            // NOCOM(#sirver): pack them together in one message?
-                                            // NOCOM(#hrapp): that can actually also fail since the
-                                            // thread_pool does not wait for its thread to
-                                            // terminate on deletion.
-                                            ipc::Message::RpcCall(rpc_call) => {
-                                                commands
-                                                    .send(swiboe::Command::RpcCall(
-                                                        client_id, rpc_call,
-                                                    ))
-                                                    .expect("RpcCall");
-                                            }
-                                            ipc::Message::RpcResponse(rpc_response) => {
-                                                commands
-                                                    .send(swiboe::Command::RpcResponse(
-                                                        rpc_response,
-                                                    ))
-                                                    .expect("RpcResponse");
+                                        let mut state = state.lock().unwrap();
+                                        match *state {
+                                            ClientState::Greeting => {
+                                                let result = on_message_before_welcome(
+                                                    &mut reader,
+                                                    &writer,
+                                                    auth_token.as_deref(),
+                                                    &plugin_token,
+                                                    message,
+                                                );
+                                                let _ = event_loop_sender
+                                                    .send(Command::ReRegisterForWriting(token));
+                                                match result {
+                                                    Ok(Some(hello)) => {
+                                                        commands
+                                                            .send(swiboe::Command::ClientConnected(
+                                                                client_id, hello,
+                                                            ))
+                                                            .expect("ClientConnected");
+                                                        *state = ClientState::Welcomed;
+                                                    }
+                                                    Ok(None) => (),
+                                                    Err(_) => {
+                                                        // We do not read anything more from
+                                                        // the client. The writing side drops
+                                                        // it once it got the refusal.
+                                                        *state = ClientState::Refused;
+                                                        return;
+                                                    }
+                                                }
                                             }
-                                            ipc::Message::RpcCancel(rpc_cancel) => {
-                                                commands
-                                                    .send(swiboe::Command::RpcCancel(rpc_cancel))
-                                                    .expect("RpcCancel");
+                                            ClientState::Welcomed => {
+                                                forward_message(&commands, client_id, message);
                                             }
+                                            // The connection is already removed or about to be,
+                                            // so we drop the reader.
+                                            ClientState::Refused | ClientState::Gone => return,
                                         }
                                     }
                                 }
                             }
+                            // Answering the TLS handshake might have filled the socket.
+                            if reader.socket.wants_write() {
+                                let _ =
+                                    event_loop_sender.send(Command::ReRegisterForWriting(token));
+                            }
                             // The ipc_bridge might have been shut down in the meantime, so ignore send
                             // errors.
                             // println!("#sirver read token: {:#?}", token);
                             let _ = event_loop_sender
-                                .send(Command::ReRegisterForReading(token, reader));
+                                .send(Command::ReRegisterForReading(client_id, reader));
                         });
                     }
                 }
@@ -321,13 +602,27 @@ impl mio::Handler for IpcBridge {
                 if events.is_writable() {
                     if let Some(conn) = self.connections.get_mut(token) {
                         let writer = conn.writer.clone();
+                        let state = conn.state.clone();
+                        let client_id = conn.client_id;
                         let event_loop_sender = event_loop.channel();
                         self.thread_pool.execute(move || {
+                            // The reading thread takes the locks the other way round.
+                            let refused = *state.lock().unwrap() == ClientState::Refused;
                             let mut writer = writer.lock().expect("writer");
                             match writer.try_write() {
                                 // NOCOM(#sirver): should disconnect instead of panic.
                                 Err(err) => panic!("Error while writing: {}", err),
-                                Ok(ipc::WriterState::AllWritten) => (),
+                                Ok(ipc::WriterState::AllWritten) => {
+                                    if refused {
+                                        // The TLS session might still hold some of the refusal.
+                                        let command = if writer.socket.wants_write() {
+                                            Command::ReRegisterForWriting(token)
+                                        } else {
+                                            Command::Disconnect(client_id)
+                                        };
+                                        let _ = event_loop_sender.send(command);
+                                    }
+                                }
                                 Ok(ipc::WriterState::MoreToWrite) => {
                                     // println!("#sirver write token: {:?}", token);
                                     // The ipc_bridge might have been shut down in the meantime, so ignore send
@@ -341,11 +636,7 @@ impl mio::Handler for IpcBridge {
                 }
 
                 if events.is_hup() {
-                    if let Some(connection) = self.connections.remove(client_token) {
-                        self.commands
-                            .send(swiboe::Command::ClientDisconnected(connection.client_id))
-                            .expect("ClientDisconnected");
-                    }
+                    self.remove_connection(client_token);
                     return;
                 }
             }
@@ -4,17 +4,43 @@
 
 use client;
 use error::Result;
+use ipc;
 use mio;
 use plugin;
+use rustls;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
+use std::sync::Arc;
 use std::thread;
+use uuid::Uuid;
 
 // NOCOM(#sirver): when a client disconnects and we still try to call one of it's rpcs, we never
 // get an error back - this will effectively interrupt the rpc call stack.
 // NOCOM(#sirver): document everything.
 
+/// Settings for a server that are not about where it listens.
+pub struct Config {
+    /// Clients that send bigger messages are disconnected. The plugins of the server itself are
+    /// not limited.
+    pub max_frame_size: usize,
+    /// If set, clients connecting over TCP must present this token. Clients connecting through
+    /// the unix domain socket are always trusted.
+    pub auth_token: Option<String>,
+    /// If set, clients connecting over TCP need to talk TLS. See 'tls::server_config'.
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_frame_size: ipc::DEFAULT_MAX_FRAME_SIZE,
+            auth_token: None,
+            tls: None,
+        }
+    }
+}
+
 pub struct Server {
     unix_domain_socket_name: PathBuf,
     tcp_addresses: Vec<String>,
@@ -29,7 +55,18 @@ pub struct Server {
 
 impl Server {
     pub fn launch(unix_domain_socket_name: &Path, tcp_addresses: &[&str]) -> Result<Self> {
+        Self::launch_with_config(unix_domain_socket_name, tcp_addresses, Config::default())
+    }
+
+    pub fn launch_with_config(
+        unix_domain_socket_name: &Path,
+        tcp_addresses: &[&str],
+        config: Config,
+    ) -> Result<Self> {
         let (tx, rx) = channel();
+        // Our plugins can send and receive messages of any size with this, so that big buffers do
+        // not get them disconnected.
+        let plugin_token = Uuid::new_v4().to_hyphenated_string();
 
         let mut event_loop = mio::EventLoop::new().expect("Could not create an event loop.");
 
@@ -54,6 +91,8 @@ impl Server {
             &mut event_loop,
             &server.unix_domain_socket_name,
             &server.tcp_addresses,
+            config,
+            plugin_token.clone(),
             server.commands.clone(),
         );
 
@@ -64,14 +103,14 @@ impl Server {
         }));
 
         server.buffer_plugin = Some(plugin::buffer::Plugin::new(
-            client::Client::connect_unix(&server.unix_domain_socket_name)
-        ?)?);
+            client::Client::connect_unix_as_plugin(&server.unix_domain_socket_name, &plugin_token)?,
+        )?);
         server.list_files_plugin = Some(plugin::list_files::Plugin::new(
-            client::Client::connect_unix(&server.unix_domain_socket_name)
-        ?)?);
+            client::Client::connect_unix_as_plugin(&server.unix_domain_socket_name, &plugin_token)?,
+        )?);
         server.log_plugin = Some(plugin::log::Plugin::new(
-            client::Client::connect_unix(&server.unix_domain_socket_name)
-        ?)?);
+            client::Client::connect_unix_as_plugin(&server.unix_domain_socket_name, &plugin_token)?,
+        )?);
         Ok(server)
     }
 
@@ -109,6 +148,7 @@ impl Server {
 }
 
 mod api_table;
+mod deadlines;
 mod ipc_bridge;
 pub mod plugin_core;
 mod swiboe; // NOCOM being a private mod
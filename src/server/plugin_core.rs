@@ -5,13 +5,85 @@
 use rpc;
 use serde::{Deserialize, Serialize};
 use serde_json;
+use server::api_table;
 use server::ipc_bridge;
 use server::swiboe;
+use std::collections::HashMap;
 
+/// The RPC is registered as soon as the server handles this request, so calls to it can reach the
+/// client before the answer does. Clients must be ready to handle it before they send this.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NewRpcRequest {
     pub priority: u16,
     pub name: String,
+    /// A JSON schema describing the arguments of the RPC. Only used for introspection.
+    #[serde(default)]
+    pub schema: Option<serde_json::Value>,
+}
+
+/// One registered handler of an RPC.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RpcInfo {
+    pub name: String,
+    pub priority: u16,
+    /// The serial of the client that registered the handler, as listed by 'core.list_clients'.
+    pub client: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeleteRpcRequest {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListRpcsResponse {
+    /// Sorted by name and, for the same name, in the order the handlers are tried.
+    pub rpcs: Vec<RpcInfo>,
+}
+
+/// What a client told about itself when it connected.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ClientInfo {
+    /// The serial of the client, as used in 'RpcInfo'.
+    pub client: u64,
+    pub name: String,
+    pub pid: u32,
+    pub version: u32,
+    pub features: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListClientsResponse {
+    /// Sorted by serial.
+    pub clients: Vec<ClientInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DescribeRpcRequest {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DescribeRpcResponse {
+    pub name: String,
+    /// The schema of the first handler that attached one.
+    pub schema: Option<serde_json::Value>,
+    pub handlers: Vec<RpcInfo>,
+}
+
+fn invalid_args(details: &str) -> rpc::Result {
+    rpc::Result::Err(rpc::Error {
+        kind: rpc::ErrorKind::InvalidArgs,
+        details: Some(serde_json::to_value(details).unwrap()),
+    })
+}
+
+fn rpc_info(name: &str, info: &api_table::ApiInfo) -> RpcInfo {
+    RpcInfo {
+        name: name.into(),
+        priority: info.priority,
+        client: info.client_id.serial,
+    }
 }
 
 pub struct CorePlugin {
@@ -23,30 +95,96 @@ impl CorePlugin {
         CorePlugin { commands: commands }
     }
 
-    pub fn call(&self, caller: ipc_bridge::ClientId, rpc_call: &rpc::Call) -> rpc::Result {
+    pub fn call(
+        &self,
+        caller: ipc_bridge::ClientId,
+        rpc_call: &rpc::Call,
+        api_table: &mut api_table::ApiTable,
+        clients: &HashMap<ipc_bridge::ClientId, ClientInfo>,
+    ) -> rpc::Result {
         match &rpc_call.function as &str {
             "core.exit" => {
                 self.commands.send(swiboe::Command::Quit).unwrap();
                 rpc::Result::success(())
             }
-            // NOCOM(#sirver): These args can be pulled out into Serializable structs.
             "core.new_rpc" => {
                 let args: NewRpcRequest = match serde_json::from_value(rpc_call.args.clone()) {
                     Ok(args) => args,
-                    // NOCOM(#sirver): report errors somehow?
-                    Err(_) => panic!("Invalid arguments"),
+                    Err(err) => return rpc::Result::Err(err.into()),
                 };
+                if args.name.starts_with(swiboe::CORE_FUNCTIONS_PREFIX) {
+                    return invalid_args("reserved_name");
+                }
+                if api_table.is_registered(&args.name, &caller) {
+                    return invalid_args("already_registered");
+                }
 
-                self.commands
-                    .send(swiboe::Command::NewRpc(caller, args.name, args.priority))
-                    .unwrap();
+                // We register right away, so that calls after this one already see the handler.
+                // The client already knows the RPC, see 'NewRpcRequest'.
+                api_table.register(
+                    args.name,
+                    api_table::ApiInfo {
+                        client_id: caller,
+                        priority: args.priority,
+                        schema: args.schema,
+                    },
+                );
                 rpc::Result::success(())
             }
-            // NOCOM(#sirver): this should not panic, but return an error.
-            _ => panic!(
-                "{} was called, but is not a core function.",
-                rpc_call.function
-            ),
+            "core.delete_rpc" => {
+                let args: DeleteRpcRequest = match serde_json::from_value(rpc_call.args.clone()) {
+                    Ok(args) => args,
+                    Err(err) => return rpc::Result::Err(err.into()),
+                };
+                // Clients can only delete what they registered themselves. RPCs that are already
+                // running on the client are not affected.
+                if !api_table.deregister(&args.name, &caller) {
+                    return rpc::Result::Err(rpc::Error {
+                        kind: rpc::ErrorKind::UnknownRpc,
+                        details: Some(serde_json::to_value(&args.name).unwrap()),
+                    });
+                }
+                rpc::Result::success(())
+            }
+            "core.list_rpcs" => {
+                let mut rpcs = Vec::new();
+                for (name, infos) in api_table.iter() {
+                    rpcs.extend(infos.iter().map(|info| rpc_info(name, info)));
+                }
+                // Handlers of one name are already in order, the sort is stable.
+                rpcs.sort_by(|a, b| a.name.cmp(&b.name));
+                rpc::Result::success(ListRpcsResponse { rpcs: rpcs })
+            }
+            "core.list_clients" => {
+                let mut clients: Vec<_> = clients.values().cloned().collect();
+                clients.sort_by_key(|info| info.client);
+                rpc::Result::success(ListClientsResponse { clients: clients })
+            }
+            "core.describe_rpc" => {
+                let args: DescribeRpcRequest = match serde_json::from_value(rpc_call.args.clone()) {
+                    Ok(args) => args,
+                    Err(err) => return rpc::Result::Err(err.into()),
+                };
+                let infos = api_table.get_all(&args.name);
+                if infos.is_empty() {
+                    return rpc::Result::Err(rpc::Error {
+                        kind: rpc::ErrorKind::UnknownRpc,
+                        details: Some(serde_json::to_value(&args.name).unwrap()),
+                    });
+                }
+                rpc::Result::success(DescribeRpcResponse {
+                    schema: infos.iter().filter_map(|info| info.schema.clone()).next(),
+                    handlers: infos
+                        .iter()
+                        .map(|info| rpc_info(&args.name, info))
+                        .collect(),
+                    name: args.name,
+                })
+            }
+            _ => rpc::Result::Err(rpc::Error {
+                kind: rpc::ErrorKind::UnknownRpc,
+                details: Some(serde_json::to_value(&rpc_call.function).unwrap()),
+            }),
         }
     }
 }
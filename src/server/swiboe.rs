@@ -7,23 +7,27 @@ use ipc;
 use mio;
 use rpc;
 use server::api_table;
+use server::deadlines;
 use server::ipc_bridge;
 use server::plugin_core;
 use spinner;
 use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, VecDeque};
 use std::sync::mpsc;
 use std::thread;
+use std::time;
 
-const CORE_FUNCTIONS_PREFIX: &'static str = "core.";
+pub const CORE_FUNCTIONS_PREFIX: &'static str = "core.";
 
 pub enum Command {
     Quit,
-    NewRpc(ipc_bridge::ClientId, String, u16),
     RpcCall(ipc_bridge::ClientId, rpc::Call),
     RpcResponse(rpc::Response),
-    RpcCancel(rpc::Cancel),
-    ClientConnected(ipc_bridge::ClientId),
+    RpcCancel(ipc_bridge::ClientId, rpc::Cancel),
+    RpcBroadcast(rpc::Broadcast),
+    RpcTimeout(String),
+    // Sent once the client said a 'Hello' that the ipc_bridge accepted.
+    ClientConnected(ipc_bridge::ClientId, ipc::Hello),
     ClientDisconnected(ipc_bridge::ClientId),
     SendDataFailed(ipc_bridge::ClientId, ipc::Message, Error),
 }
@@ -33,6 +37,42 @@ struct RunningRpc {
     caller: ipc_bridge::ClientId,
     callee: ipc_bridge::ClientId,
     rpc_call: rpc::Call,
+    // The handlers after 'callee' in the order they are tried, as they were registered when the
+    // RPC was called. Handlers registered later are never tried, so that falling through on
+    // NotHandled does not depend on what other clients do meanwhile.
+    next_callees: VecDeque<ipc_bridge::ClientId>,
+}
+
+impl RunningRpc {
+    fn new(
+        caller: ipc_bridge::ClientId,
+        rpc_call: rpc::Call,
+        api_table: &api_table::ApiTable,
+    ) -> Option<Self> {
+        let mut callees: VecDeque<_> = api_table
+            .get_all(&rpc_call.function)
+            .iter()
+            .map(|info| info.client_id)
+            .collect();
+        let callee = callees.pop_front()?;
+        Some(RunningRpc {
+            caller: caller,
+            callee: callee,
+            rpc_call: rpc_call,
+            next_callees: callees,
+        })
+    }
+
+    /// Moves on to the next handler that is still registered. Returns false if there is none.
+    fn advance(&mut self, api_table: &api_table::ApiTable) -> bool {
+        while let Some(callee) = self.next_callees.pop_front() {
+            if api_table.is_registered(&self.rpc_call.function, &callee) {
+                self.callee = callee;
+                return true;
+            }
+        }
+        false
+    }
 }
 
 pub type SenderTo = mpsc::Sender<Command>;
@@ -58,10 +98,11 @@ impl spinner::Receiver<Command> for Receiver {
 
 pub struct Handler {
     api_table: api_table::ApiTable,
-    clients: HashSet<ipc_bridge::ClientId>,
+    clients: HashMap<ipc_bridge::ClientId, plugin_core::ClientInfo>,
     ipc_bridge_commands: mio::Sender<ipc_bridge::Command>,
     running_rpcs: HashMap<String, RunningRpc>,
     plugin_core: plugin_core::CorePlugin,
+    deadlines: deadlines::Deadlines,
 }
 
 impl Handler {
@@ -71,21 +112,173 @@ impl Handler {
     ) -> Self {
         Handler {
             api_table: api_table::ApiTable::new(),
-            clients: HashSet::new(),
+            clients: HashMap::new(),
             running_rpcs: HashMap::new(),
             ipc_bridge_commands: ipc_bridge_commands,
-            plugin_core: plugin_core::CorePlugin::new(commands_sender),
+            plugin_core: plugin_core::CorePlugin::new(commands_sender.clone()),
+            deadlines: deadlines::Deadlines::spawn(commands_sender),
+        }
+    }
+
+    // Lets us know once the deadline of 'rpc_call' passed.
+    fn watch_deadline(&self, rpc_call: &rpc::Call) {
+        let deadline_ms = match rpc_call.deadline_ms {
+            Some(deadline_ms) => deadline_ms,
+            None => return,
+        };
+        self.deadlines.watch(
+            rpc_call.context.clone(),
+            time::Duration::from_millis(deadline_ms),
+        );
+    }
+
+    // Removes the RPC 'context' once it is done, so that its deadline no longer needs watching.
+    fn remove_running_rpc(&mut self, context: &str) -> Option<RunningRpc> {
+        let running_rpc = self.running_rpcs.remove(context)?;
+        if running_rpc.rpc_call.deadline_ms.is_some() {
+            self.deadlines.forget(context.to_string());
+        }
+        Some(running_rpc)
+    }
+
+    // Cancels the RPC 'context' and, recursively, all RPCs its callee made on its behalf. The
+    // callers of these nested RPCs get a 'Cancelled' error, the caller of 'context' is not told.
+    fn cancel_rpc(&mut self, context: &str) -> Result<Option<RunningRpc>> {
+        let running_rpc = match self.remove_running_rpc(context) {
+            Some(running_rpc) => running_rpc,
+            None => return Ok(None),
+        };
+        self.ipc_bridge_commands
+            .send(ipc_bridge::Command::SendData(
+                running_rpc.callee,
+                ipc::Message::RpcCancel(rpc::Cancel {
+                    context: context.into(),
+                }),
+            ))?;
+
+        let nested_contexts: Vec<_> = self
+            .running_rpcs
+            .iter()
+            .filter(|&(_, nested_rpc)| match nested_rpc.rpc_call.parent {
+                Some(ref parent) => parent == context,
+                None => false,
+            })
+            .map(|(nested_context, _)| nested_context.clone())
+            .collect();
+        for nested_context in nested_contexts {
+            if let Some(nested_rpc) = self.cancel_rpc(&nested_context)? {
+                self.ipc_bridge_commands
+                    .send(ipc_bridge::Command::SendData(
+                        nested_rpc.caller,
+                        ipc::Message::RpcResponse(rpc::Response {
+                            context: nested_context,
+                            kind: rpc::ResponseKind::Last(rpc::Result::Err(rpc::Error {
+                                kind: rpc::ErrorKind::Cancelled,
+                                details: None,
+                            })),
+                        }),
+                    ))?;
+            }
+        }
+        Ok(Some(running_rpc))
+    }
+
+    fn on_rpc_timeout(&mut self, context: String) -> Result<()> {
+        // The RPC might have finished in time.
+        let running_rpc = match self.cancel_rpc(&context)? {
+            Some(running_rpc) => running_rpc,
+            None => return Ok(()),
+        };
+        self.ipc_bridge_commands
+            .send(ipc_bridge::Command::SendData(
+                running_rpc.caller,
+                ipc::Message::RpcResponse(rpc::Response {
+                    context: context,
+                    kind: rpc::ResponseKind::Last(rpc::Result::Err(rpc::Error {
+                        kind: rpc::ErrorKind::Timeout,
+                        details: None,
+                    })),
+                }),
+            ))?;
+        Ok(())
+    }
+
+    fn on_rpc_cancel(
+        &mut self,
+        client_id: ipc_bridge::ClientId,
+        rpc_cancel: rpc::Cancel,
+    ) -> Result<()> {
+        // Simply drop this message for unknown RPCs and RPCs that somebody else called.
+        let is_caller = match self.running_rpcs.get(&rpc_cancel.context) {
+            Some(running_rpc) => running_rpc.caller == client_id,
+            None => false,
+        };
+        if is_caller {
+            self.cancel_rpc(&rpc_cancel.context)?;
+        }
+        Ok(())
+    }
+
+    fn on_client_disconnected(&mut self, client_id: ipc_bridge::ClientId) -> Result<()> {
+        self.clients.remove(&client_id);
+
+        // Cancel all pending RPCs that have been requested by this client.
+        let rpcs_to_cancel: Vec<_> = self
+            .running_rpcs
+            .iter()
+            .filter_map(|(context, running_rpc)| {
+                if running_rpc.caller == client_id {
+                    Some(context.to_string())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        for context in rpcs_to_cancel {
+            self.cancel_rpc(&context)?;
         }
+
+        // RPCs this client was working on will never be answered. We pass them on to the next
+        // handler as if it had answered NotHandled, or fail them if there is none.
+        self.api_table.deregister_by_client(&client_id);
+        let orphaned_rpcs: Vec<_> = self
+            .running_rpcs
+            .iter()
+            .filter(|&(_, running_rpc)| running_rpc.callee == client_id)
+            .map(|(context, _)| context.clone())
+            .collect();
+        for context in orphaned_rpcs {
+            let running_rpc = self.running_rpcs.get_mut(&context).unwrap();
+            if running_rpc.advance(&self.api_table) {
+                self.ipc_bridge_commands
+                    .send(ipc_bridge::Command::SendData(
+                        running_rpc.callee,
+                        ipc::Message::RpcCall(running_rpc.rpc_call.clone()),
+                    ))?;
+            } else {
+                let running_rpc = self.remove_running_rpc(&context).unwrap();
+                self.ipc_bridge_commands
+                    .send(ipc_bridge::Command::SendData(
+                        running_rpc.caller,
+                        ipc::Message::RpcResponse(rpc::Response {
+                            context: context,
+                            kind: rpc::ResponseKind::Last(rpc::Result::Err(rpc::Error {
+                                kind: rpc::ErrorKind::CalleeDisconnected,
+                                details: None,
+                            })),
+                        }),
+                    ))?;
+            }
+        }
+        Ok(())
     }
 
-    fn on_rpc_cancel(&mut self, rpc_cancel: rpc::Cancel) -> Result<()> {
-        // NOCOM(#sirver): only the original caller can cancel, really.
-        // Simply drop this message for unknown RPC
-        if let Some(running_rpc) = self.running_rpcs.get(&rpc_cancel.context) {
+    fn on_rpc_broadcast(&mut self, rpc_broadcast: rpc::Broadcast) -> Result<()> {
+        for info in self.api_table.get_all(&rpc_broadcast.function) {
             self.ipc_bridge_commands
                 .send(ipc_bridge::Command::SendData(
-                    running_rpc.callee,
-                    ipc::Message::RpcCancel(rpc_cancel),
+                    info.client_id,
+                    ipc::Message::RpcBroadcast(rpc_broadcast.clone()),
                 ))?;
         }
         Ok(())
@@ -125,38 +318,24 @@ impl Handler {
                         ))?;
                 }
                 rpc::Result::NotHandled => {
-                    // TODO(sirver): If a new function has been registered or been deleted since we
-                    // last saw this context, this might skip a handler or call one twice. We need
-                    // a better way to keep track where we are in the list of handlers.
-                    let running_rpc = running_rpc.get_mut();
-
-                    // NOCOM(#sirver): quite some code duplication with RpcCall
-                    match self
-                        .api_table
-                        .get_next(&running_rpc.rpc_call.function, &running_rpc.callee)
-                    {
-                        Some(info) => {
-                            // NOCOM(#sirver): eventually, when we keep proper track of our rpc calls, this should be
-                            // able to move again.
-                            self.ipc_bridge_commands
-                                .send(ipc_bridge::Command::SendData(
-                                    info.client_id,
-                                    ipc::Message::RpcCall(running_rpc.rpc_call.clone()),
-                                ))?;
-                            running_rpc.callee = info.client_id;
-                        }
-                        None => {
-                            self.ipc_bridge_commands
-                                .send(ipc_bridge::Command::SendData(
-                                    running_rpc.caller,
-                                    ipc::Message::RpcResponse(rpc::Response {
-                                        context: running_rpc.rpc_call.context.clone(),
-                                        kind: rpc::ResponseKind::Last(rpc::Result::NotHandled),
-                                    }),
-                                ))?;
-                        }
-                    };
-                    // NOCOM(#sirver): we ignore timeouts.
+                    if running_rpc.get_mut().advance(&self.api_table) {
+                        let running_rpc = running_rpc.get();
+                        self.ipc_bridge_commands
+                            .send(ipc_bridge::Command::SendData(
+                                running_rpc.callee,
+                                ipc::Message::RpcCall(running_rpc.rpc_call.clone()),
+                            ))?;
+                    } else {
+                        let running_rpc = running_rpc.remove();
+                        self.ipc_bridge_commands
+                            .send(ipc_bridge::Command::SendData(
+                                running_rpc.caller,
+                                ipc::Message::RpcResponse(rpc::Response {
+                                    context: running_rpc.rpc_call.context,
+                                    kind: rpc::ResponseKind::Last(rpc::Result::NotHandled),
+                                }),
+                            ))?;
+                    }
                 }
             },
         };
@@ -168,27 +347,18 @@ impl spinner::Handler<Command> for Handler {
     fn handle(&mut self, command: Command) -> Result<spinner::Command> {
         match command {
             Command::Quit => Ok(spinner::Command::Quit),
-            Command::NewRpc(client_id, name, priority) => {
-                // NOCOM(#sirver): deny everything starting with 'core'
-                // NOCOM(#sirver): make sure the client_id is known.
-                // NOCOM(#sirver): make sure the client has not already registered this
-                // function.
-                self.api_table.register(
-                    name,
-                    api_table::ApiInfo {
-                        client_id: client_id,
-                        priority: priority,
-                    },
-                );
-                Ok(spinner::Command::Continue)
-            }
             Command::RpcCall(client_id, rpc_call) => {
                 // NOCOM(#sirver): make sure this is not already in running_rpcs.
                 // NOCOM(#sirver): function name might not be in there.
 
                 // Special case 'core.'. We handle them immediately.
                 if rpc_call.function.starts_with(CORE_FUNCTIONS_PREFIX) {
-                    let result = self.plugin_core.call(client_id, &rpc_call);
+                    let result = self.plugin_core.call(
+                        client_id,
+                        &rpc_call,
+                        &mut self.api_table,
+                        &self.clients,
+                    );
                     self.ipc_bridge_commands
                         .send(ipc_bridge::Command::SendData(
                             client_id,
@@ -198,23 +368,16 @@ impl spinner::Handler<Command> for Handler {
                             }),
                         ))?;
                 } else {
-                    match self.api_table.get_first(&rpc_call.function) {
-                        Some(info) => {
-                            // NOCOM(#sirver): eventually, when we keep proper track of our rpc calls, this should be
-                            // able to move again.
-                            self.running_rpcs.insert(
-                                rpc_call.context.clone(),
-                                RunningRpc {
-                                    caller: client_id,
-                                    callee: info.client_id,
-                                    rpc_call: rpc_call.clone(),
-                                },
-                            );
+                    match RunningRpc::new(client_id, rpc_call.clone(), &self.api_table) {
+                        Some(running_rpc) => {
+                            let callee = running_rpc.callee;
+                            self.running_rpcs
+                                .insert(rpc_call.context.clone(), running_rpc);
+                            self.watch_deadline(&rpc_call);
                             self.ipc_bridge_commands.send(ipc_bridge::Command::SendData(
-                                info.client_id,
+                                callee,
                                 ipc::Message::RpcCall(rpc_call)
                             ))?;
-                            // NOCOM(#sirver): we ignore timeouts.
                         }
                         None => {
                             self.ipc_bridge_commands.send(ipc_bridge::Command::SendData(
@@ -236,13 +399,26 @@ impl spinner::Handler<Command> for Handler {
                 self.on_rpc_response(rpc_response)?;
                 Ok(spinner::Command::Continue)
             }
-            Command::RpcCancel(rpc_cancel) => {
-                self.on_rpc_cancel(rpc_cancel)?;
+            Command::RpcCancel(client_id, rpc_cancel) => {
+                self.on_rpc_cancel(client_id, rpc_cancel)?;
+                Ok(spinner::Command::Continue)
+            }
+            Command::RpcBroadcast(rpc_broadcast) => {
+                self.on_rpc_broadcast(rpc_broadcast)?;
+                Ok(spinner::Command::Continue)
+            }
+            Command::RpcTimeout(context) => {
+                self.on_rpc_timeout(context)?;
                 Ok(spinner::Command::Continue)
             }
             Command::SendDataFailed(client_id, msg, err) => {
                 let action = match msg {
-                    ipc::Message::RpcResponse(_) | ipc::Message::RpcCancel(_) => {
+                    ipc::Message::RpcResponse(_)
+                    | ipc::Message::RpcCancel(_)
+                    | ipc::Message::RpcBroadcast(_)
+                    | ipc::Message::Hello(_)
+                    | ipc::Message::Welcome(_)
+                    | ipc::Message::Refused(_) => {
                         // NOCOM(#sirver): on a streaming rpc, this should also try to cancel
                         // the RPC.
                         "dropped the RpcResponse/RpcCall."
@@ -258,31 +434,22 @@ impl spinner::Handler<Command> for Handler {
                 println!("Sending to {:?} failed: {:?}, {}", client_id, err, action);
                 Ok(spinner::Command::Continue)
             }
-            Command::ClientConnected(client_id) => {
+            Command::ClientConnected(client_id, hello) => {
                 // NOCOM(#sirver): make sure client_id is not yet known.
-                self.clients.insert(client_id);
+                self.clients.insert(
+                    client_id,
+                    plugin_core::ClientInfo {
+                        client: client_id.serial,
+                        name: hello.name,
+                        pid: hello.pid,
+                        version: hello.version,
+                        features: hello.features,
+                    },
+                );
                 Ok(spinner::Command::Continue)
             }
             Command::ClientDisconnected(client_id) => {
-                self.clients.remove(&client_id);
-
-                // Kill all pending RPCs that have been requested by this client.
-                let rpcs_to_remove: Vec<_> = self
-                    .running_rpcs
-                    .iter()
-                    .filter_map(|(context, running_rpc)| {
-                        if running_rpc.caller == client_id {
-                            Some(context.to_string())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-                for context in rpcs_to_remove {
-                    self.running_rpcs.remove(&context);
-                }
-
-                self.api_table.deregister_by_client(&client_id);
+                self.on_client_disconnected(client_id)?;
                 Ok(spinner::Command::Continue)
             }
         }
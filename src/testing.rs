@@ -2,7 +2,7 @@
 // Licensed under the Apache License, Version 2.0. See LICENSE.txt
 // in the project root for license information.
 
-use server::Server;
+use server::{Config, Server};
 use std::path::PathBuf;
 use tempdir::TempDir;
 
@@ -14,12 +14,16 @@ pub struct TestHarness {
 
 impl TestHarness {
     pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
         let temp_directory = TempDir::new("swiboe").unwrap();
 
         let mut socket_name = temp_directory.path().to_path_buf();
         socket_name.push("_socket");
 
-        let server = Server::launch(&socket_name, &[]).unwrap();
+        let server = Server::launch_with_config(&socket_name, &[], config).unwrap();
 
         TestHarness {
             server: Some(server),
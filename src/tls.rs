@@ -0,0 +1,199 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+// TLS for TCP connections. Certificates and keys are read from PEM files.
+
+use error::{Error, Result};
+use rustls;
+use rustls::pki_types::ServerName;
+use rustls_pemfile;
+use std::convert::TryFrom;
+use std::error;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// How much we read from the socket at once. The decrypted data of it always fits into the buffers
+// of the session.
+const READ_CHUNK_SIZE: usize = 4096;
+
+fn invalid_data<E: Into<Box<dyn error::Error + Send + Sync>>>(error: E) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, error).into()
+}
+
+fn crypto_provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let certificates = rustls_pemfile::certs(&mut reader).collect::<io::Result<Vec<_>>>()?;
+    if certificates.is_empty() {
+        return Err(invalid_data(format!(
+            "{} does not contain certificates",
+            path.display()
+        )));
+    }
+    Ok(certificates)
+}
+
+/// Creates the TLS config for a server that presents the certificate chain in
+/// 'certificate_file'. 'private_key_file' contains the key of the first certificate.
+pub fn server_config(
+    certificate_file: &Path,
+    private_key_file: &Path,
+) -> Result<Arc<rustls::ServerConfig>> {
+    let certificates = read_certificates(certificate_file)?;
+    let mut reader = BufReader::new(fs::File::open(private_key_file)?);
+    let private_key = rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| {
+        invalid_data(format!(
+            "{} does not contain a private key",
+            private_key_file.display()
+        ))
+    })?;
+    let config = rustls::ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?
+        .with_no_client_auth()
+        .with_single_cert(certificates, private_key)
+        .map_err(invalid_data)?;
+    Ok(Arc::new(config))
+}
+
+/// Creates the TLS config for a client that trusts the certificates in 'ca_file' and no others.
+pub fn client_config(ca_file: &Path) -> Result<Arc<rustls::ClientConfig>> {
+    let mut roots = rustls::RootCertStore::empty();
+    for certificate in read_certificates(ca_file)? {
+        roots.add(certificate).map_err(invalid_data)?;
+    }
+    let config = rustls::ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// A session for talking to the server with the name 'server_name', which its certificate needs
+/// to be issued for.
+pub fn client_session(
+    config: Arc<rustls::ClientConfig>,
+    server_name: &str,
+) -> Result<rustls::Connection> {
+    let server_name = ServerName::try_from(server_name.to_string()).map_err(invalid_data)?;
+    let session = rustls::ClientConnection::new(config, server_name).map_err(invalid_data)?;
+    Ok(session.into())
+}
+
+pub fn server_session(config: Arc<rustls::ServerConfig>) -> Result<rustls::Connection> {
+    let session = rustls::ServerConnection::new(config).map_err(invalid_data)?;
+    Ok(session.into())
+}
+
+// Sends what the session has for our peer, until the socket would block.
+fn write_pending<S: Write>(session: &mut rustls::Connection, socket: &mut S) -> io::Result<()> {
+    while session.wants_write() {
+        match session.write_tls(socket) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(_) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Encrypts everything that goes over 'socket'. Like sockets, a stream can be shared between a
+/// reading and a writing thread: both halves use clones of the socket and the same session. Works
+/// with blocking and non-blocking sockets.
+pub struct TlsStream<S> {
+    socket: S,
+    session: Arc<Mutex<rustls::Connection>>,
+}
+
+impl<S> TlsStream<S> {
+    pub fn new(socket: S, session: rustls::Connection) -> Self {
+        TlsStream {
+            socket: socket,
+            session: Arc::new(Mutex::new(session)),
+        }
+    }
+
+    /// Returns a stream for 'socket', which must be a clone of our socket, that uses the same
+    /// session.
+    pub fn share(&self, socket: S) -> Self {
+        TlsStream {
+            socket: socket,
+            session: self.session.clone(),
+        }
+    }
+
+    pub fn socket(&self) -> &S {
+        &self.socket
+    }
+
+    /// True if encrypted data is waiting for the socket to become writable.
+    pub fn wants_write(&self) -> bool {
+        self.session.lock().unwrap().wants_write()
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            {
+                let mut session = self.session.lock().unwrap();
+                match session.reader().read(buf) {
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                    result => return result,
+                }
+            }
+
+            // We do not hold the lock while waiting for our peer, so that the other half of the
+            // stream can write meanwhile.
+            let mut data = [0u8; READ_CHUNK_SIZE];
+            let num_read = self.socket.read(&mut data)?;
+            if num_read == 0 {
+                return Ok(0);
+            }
+
+            let mut session = self.session.lock().unwrap();
+            let mut data = &data[..num_read];
+            while !data.is_empty() {
+                session.read_tls(&mut data)?;
+                if let Err(err) = session.process_new_packets() {
+                    // Tell our peer what went wrong, if we can.
+                    let _ = write_pending(&mut session, &mut self.socket);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                }
+            }
+            // The handshake might want to answer.
+            write_pending(&mut session, &mut self.socket)?;
+        }
+    }
+}
+
+impl<S: Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut session = self.session.lock().unwrap();
+        // We do not take more data while the socket cannot keep up.
+        write_pending(&mut session, &mut self.socket)?;
+        if session.wants_write() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let num_written = session.writer().write(buf)?;
+        write_pending(&mut session, &mut self.socket)?;
+        Ok(num_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut session = self.session.lock().unwrap();
+        write_pending(&mut session, &mut self.socket)?;
+        if session.wants_write() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.socket.flush()
+    }
+}
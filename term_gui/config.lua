@@ -34,16 +34,18 @@ in_insert_mode = function() return CURRENT_MODE == "insert" end
    -- -- })
 -- -- end)
 
-swiboe.map {
-   keys = { "i" },
+-- Mappings win over the keys of the buffer view, so this would take 'i' away
+-- from it and we could never insert text.
+-- swiboe.map {
+   -- keys = { "i" },
    -- when = in_normal_mode,
    -- priority = 1000,
-   execute = function()
-      print("--> ", CURRENT_MODE)
-      CURRENT_MODE = "insert"
-      print("--> ", CURRENT_MODE)
-   end,
-}
+   -- execute = function()
+      -- print("--> ", CURRENT_MODE)
+      -- CURRENT_MODE = "insert"
+      -- print("--> ", CURRENT_MODE)
+   -- end,
+-- }
 
 swiboe.map {
    keys = { "s" },
@@ -68,9 +70,7 @@ swiboe.map {
    execute = function(client)
       -- NOCOM(#sirver): need to implement our own lua table to JSON converter eventually.
       local args = JSON:encode_pretty {
-          -- NOCOM(#sirver): for now, we move any cursor, but we need to expose
-          -- which cursor should be moved somehow for this method.
-          cursor_id = "every_cursor_currently_has_this_id",
+          cursor_id = swiboe.current_cursor_id,
           delta = { line_index = -1, column_index = 0, },
        }
        -- NOCOM(#sirver): this should return an RPC object, but for now, we just implicitly wait.
@@ -85,9 +85,7 @@ swiboe.map {
    execute = function(client)
       -- NOCOM(#sirver): need to implement our own lua table to JSON converter eventually.
       local args = JSON:encode_pretty {
-          -- NOCOM(#sirver): for now, we move any cursor, but we need to expose
-          -- which cursor should be moved somehow for this method.
-          cursor_id = "every_cursor_currently_has_this_id",
+          cursor_id = swiboe.current_cursor_id,
           delta = { line_index = 1, column_index = 0, },
        }
        -- NOCOM(#sirver): this should return an RPC object, but for now, we just implicitly wait.
@@ -102,9 +100,7 @@ swiboe.map {
    execute = function(client)
       -- NOCOM(#sirver): need to implement our own lua table to JSON converter eventually.
       local args = JSON:encode_pretty {
-          -- NOCOM(#sirver): for now, we move any cursor, but we need to expose
-          -- which cursor should be moved somehow for this method.
-          cursor_id = "every_cursor_currently_has_this_id",
+          cursor_id = swiboe.current_cursor_id,
           delta = { line_index = 0, column_index = -1, },
        }
        -- NOCOM(#sirver): this should return an RPC object, but for now, we just implicitly wait.
@@ -119,9 +115,7 @@ swiboe.map {
    execute = function(client)
       -- NOCOM(#sirver): need to implement our own lua table to JSON converter eventually.
       local args = JSON:encode_pretty {
-          -- NOCOM(#sirver): for now, we move any cursor, but we need to expose
-          -- which cursor should be moved somehow for this method.
-          cursor_id = "every_cursor_currently_has_this_id",
+          cursor_id = swiboe.current_cursor_id,
           delta = { line_index = 0, column_index = 1, },
        }
        -- NOCOM(#sirver): this should return an RPC object, but for now, we just implicitly wait.
@@ -139,9 +133,7 @@ swiboe.map {
    execute = function(client)
       -- NOCOM(#sirver): need to implement our own lua table to JSON converter eventually.
       local args = JSON:encode_pretty {
-          -- NOCOM(#sirver): for now, we move any cursor, but we need to expose
-          -- which cursor should be moved somehow for this method.
-          cursor_id = "every_cursor_currently_has_this_id",
+          cursor_id = swiboe.current_cursor_id,
           delta = { line_index = MIN, column_index = MIN, },
        }
        -- NOCOM(#sirver): this should return an RPC object, but for now, we just implicitly wait.
@@ -161,23 +161,116 @@ struct BufferViewWidget {
     view_id: String,
     client: client::ThinClient,
     cursor_id: String,
+    // True while keys type text instead of editing commands.
+    inserting: bool,
 }
 
 impl BufferViewWidget {
     pub fn new(view_id: String, client: client::ThinClient) -> Self {
         BufferViewWidget {
+            cursor_id: buffer_views::primary_cursor_id(&view_id),
             view_id: view_id,
             client: client,
-            cursor_id: String::new(),
+            inserting: false,
         }
     }
 
+    // Calls 'function' and waits for it, since dropping the call would cancel it.
+    fn call<T: Serialize>(&mut self, function: &str, args: &T) -> swiboe::Result<()> {
+        let mut rpc = try!(self.client.call(function, args));
+        try!(rpc.wait());
+        Ok(())
+    }
+
+    // Edits the buffer for 'key'. Returns false if the key is not meant for the buffer view.
+    fn on_key(&mut self, key: rustbox::Key, buffer_views: &RwLock<buffer_views::BufferViews>) -> swiboe::Result<bool> {
+        // We must not hold the lock while calling, the buffer views answer our calls.
+        let (position, last_cursor_id, last_position, num_cursors) = {
+            let buffer_views = buffer_views.read().unwrap();
+            let buffer_view = buffer_views.get(&self.view_id).unwrap();
+            let last_cursor = buffer_view.cursors.last().unwrap();
+            (buffer_view.cursors[0].position, last_cursor.id().to_string(),
+             last_cursor.position, buffer_view.cursors.len())
+        };
+
+        let text = match key {
+            rustbox::Key::Esc if self.inserting => {
+                self.inserting = false;
+                return Ok(true);
+            },
+            rustbox::Key::Char(c) if self.inserting => c.to_string(),
+            rustbox::Key::Enter if self.inserting => "\n".into(),
+            rustbox::Key::Tab if self.inserting => "\t".into(),
+            rustbox::Key::Char('i') => {
+                self.inserting = true;
+                return Ok(true);
+            },
+            rustbox::Key::Backspace => {
+                let view_id = self.view_id.clone();
+                try!(self.call("gui.buffer_view.delete", &buffer_views::DeleteRequest {
+                    buffer_view_id: view_id,
+                }));
+                return Ok(true);
+            },
+            rustbox::Key::Home | rustbox::Key::End => {
+                let column_index = match key {
+                    rustbox::Key::Home => 0,
+                    _ => isize::max_value(),
+                };
+                let cursor_id = self.cursor_id.clone();
+                try!(self.call("gui.buffer_view.set_cursor", &buffer_views::SetCursorRequest {
+                    cursor_id: cursor_id,
+                    position: buffer_views::Position {
+                        line_index: position.line_index,
+                        column_index: column_index,
+                    },
+                    extend_selection: false,
+                }));
+                return Ok(true);
+            },
+            // Adds a cursor below the last one.
+            rustbox::Key::Ctrl('n') => {
+                let view_id = self.view_id.clone();
+                try!(self.call("gui.buffer_view.add_cursor", &buffer_views::AddCursorRequest {
+                    buffer_view_id: view_id,
+                    position: buffer_views::Position {
+                        line_index: last_position.line_index + 1,
+                        column_index: last_position.column_index,
+                    },
+                }));
+                return Ok(true);
+            },
+            rustbox::Key::Ctrl('r') => {
+                if num_cursors > 1 {
+                    try!(self.call("gui.buffer_view.remove_cursor", &buffer_views::RemoveCursorRequest {
+                        cursor_id: last_cursor_id,
+                    }));
+                }
+                return Ok(true);
+            },
+            rustbox::Key::Esc => {
+                let view_id = self.view_id.clone();
+                try!(self.call("gui.buffer_view.clear_cursors", &buffer_views::ClearCursorsRequest {
+                    buffer_view_id: view_id,
+                }));
+                return Ok(true);
+            },
+            _ => return Ok(false),
+        };
+
+        let view_id = self.view_id.clone();
+        try!(self.call("gui.buffer_view.insert", &buffer_views::InsertRequest {
+            buffer_view_id: view_id,
+            text: text,
+        }));
+        Ok(true)
+    }
+
     fn draw(&mut self, buffer_view: &buffer_views::BufferView, rustbox: &rustbox::RustBox) {
         let mut row = 0;
         let top_line_index = buffer_view.top_line_index as usize;
-        self.cursor_id = buffer_view.cursor.id().to_string();
 
-        let mut cursor_drawn = false;
+        let mut cursors_drawn = vec![false; buffer_view.cursors.len()];
         while row < rustbox.height() {
             let line_index = top_line_index + row;
             if let Some(line) = buffer_view.lines.get(line_index) {
@@ -185,22 +278,34 @@ impl BufferViewWidget {
                     if col >= rustbox.width() {
                         break;
                     }
-                    let bg = if buffer_view.cursor.position.line_index == line_index as isize &&
-                        buffer_view.cursor.position.column_index as usize == col {
-                        cursor_drawn = true;
-                        Color::Red
-                    } else {
-                        Color::Default
+                    let position = buffer_views::Position {
+                        line_index: line_index as isize,
+                        column_index: col as isize,
                     };
+                    let mut bg = Color::Default;
+                    for (index, cursor) in buffer_view.cursors.iter().enumerate() {
+                        let (start, end) = cursor.selection();
+                        if cursor.position == position {
+                            cursors_drawn[index] = true;
+                            bg = Color::Red;
+                            break;
+                        } else if start <= position && position < end {
+                            bg = Color::Blue;
+                        }
+                    }
                     rustbox.print_char(col, row, rustbox::RB_NORMAL, Color::Default, bg, c);
                 }
             }
             row += 1;
         }
 
-        if !cursor_drawn {
-            let row = buffer_view.cursor.position.line_index - top_line_index as isize;
-            rustbox.print_char(buffer_view.cursor.position.column_index as usize,
+        // Cursors at the end of a line are not on a character.
+        for (index, cursor) in buffer_view.cursors.iter().enumerate() {
+            if cursors_drawn[index] || (cursor.position.line_index as usize) < top_line_index {
+                continue;
+            }
+            let row = cursor.position.line_index - top_line_index as isize;
+            rustbox.print_char(cursor.position.column_index as usize,
                                row as usize, rustbox::RB_NORMAL,
                                Color::Default, Color::Red, ' ');
         }
@@ -288,6 +393,7 @@ impl TerminalGui {
 
                             let mut buffer_views = self.buffer_views.write().unwrap();
                             let view_id = buffer_views.new_view(response.buffer_index, self.rustbox.width(), self.rustbox.height());
+                            self.config_file_runner.set_current_cursor_id(&gui::buffer_views::primary_cursor_id(&view_id));
                             self.buffer_view_widget = Some(BufferViewWidget::new(view_id, try!(self.client.clone())));
                         },
                     }
@@ -319,45 +425,58 @@ impl TerminalGui {
         };
         let delta_t_in_seconds = delta_t.num_nanoseconds().unwrap() as f64 / 1e9;
 
-        match key {
+        // Typed text belongs to the buffer. Everything else goes to the keymap first, so that
+        // mappings of the config file win over the keys the buffer view knows itself.
+        if let Some(ref mut widget) = self.buffer_view_widget {
+            if widget.inserting && try!(widget.on_key(key, &self.buffer_views)) {
+                return Ok(true);
+            }
+        }
+
+        let keymap = &mut self.config_file_runner.keymap_handler;
+        let mapped = match key {
             // NOCOM(#sirver): should be handled through plugins.
             rustbox::Key::Char('q') => return Ok(false),
             rustbox::Key::Ctrl('t') => {
-                self.completer = Some(try!(CompleterWidget::new(&mut self.client)))
+                self.completer = Some(try!(CompleterWidget::new(&mut self.client)));
+                return Ok(true);
             },
             rustbox::Key::Esc => {
-                self.config_file_runner.keymap_handler.timeout();
+                keymap.timeout();
+                false
             },
             rustbox::Key::Char(a) => {
-                self.config_file_runner.keymap_handler.key_down(
-                    delta_t_in_seconds, keymap_handler::Key::Char(a));
+                keymap.key_down(delta_t_in_seconds, keymap_handler::Key::Char(a))
             },
             rustbox::Key::Up => {
-                self.config_file_runner.keymap_handler.key_down(
-                    delta_t_in_seconds, keymap_handler::Key::Up);
+                keymap.key_down(delta_t_in_seconds, keymap_handler::Key::Up)
             },
             rustbox::Key::Down => {
-                self.config_file_runner.keymap_handler.key_down(
-                    delta_t_in_seconds, keymap_handler::Key::Down);
+                keymap.key_down(delta_t_in_seconds, keymap_handler::Key::Down)
             },
             rustbox::Key::Left => {
-                self.config_file_runner.keymap_handler.key_down(
-                    delta_t_in_seconds, keymap_handler::Key::Left);
+                keymap.key_down(delta_t_in_seconds, keymap_handler::Key::Left)
             },
             rustbox::Key::Right => {
-                self.config_file_runner.keymap_handler.key_down(
-                    delta_t_in_seconds, keymap_handler::Key::Right);
+                keymap.key_down(delta_t_in_seconds, keymap_handler::Key::Right)
             },
             rustbox::Key::Tab => {
-                self.config_file_runner.keymap_handler.key_down(
-                    delta_t_in_seconds, keymap_handler::Key::Tab);
+                keymap.key_down(delta_t_in_seconds, keymap_handler::Key::Tab)
             },
             rustbox::Key::Ctrl(some_other_key) => {
-                self.config_file_runner.keymap_handler.key_down(
-                    delta_t_in_seconds, keymap_handler::Key::Ctrl);
-                try!(self.handle_key(rustbox::Key::Char(some_other_key)));
+                // Both keys come in one event, so they form a chord.
+                keymap.key_down(delta_t_in_seconds, keymap_handler::Key::Ctrl) ||
+                    keymap.key_down(0., keymap_handler::Key::Char(some_other_key))
+            }
+            _ => false,
+        };
+
+        if !mapped {
+            if let Some(ref mut widget) = self.buffer_view_widget {
+                if try!(widget.on_key(key, &self.buffer_views)) {
+                    keymap.clear();
+                }
             }
-            _ => (),
         }
         Ok(true)
     }
@@ -2,16 +2,29 @@
 // Licensed under the Apache License, Version 2.0. See LICENSE.txt
 // in the project root for license information.
 
+use rcgen;
+use rmp_serde;
+use serde::Deserialize;
 use serde_json;
 use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net;
 use std::path;
 use std::sync;
 use std::thread;
+use std::time;
+use swiboe;
 use swiboe::client;
 use swiboe::client::RpcCaller;
+use swiboe::ipc;
 use swiboe::rpc;
-use swiboe::server::Server;
+use swiboe::server::plugin_core;
+use swiboe::server::{Config, Server};
 use swiboe::testing::TestHarness;
+use swiboe::tls;
+use tempdir::TempDir;
+use unix_socket::UnixStream;
 use uuid::Uuid;
 use CallbackRpc;
 
@@ -287,6 +300,101 @@ fn call_streaming_rpc_simple() {
     );
 }
 
+#[derive(Deserialize, Debug, PartialEq)]
+struct Message {
+    msg: String,
+}
+
+#[test]
+fn call_streaming_rpc_typed() {
+    let t = TestHarness::new();
+
+    let mut streaming_client = client::Client::connect_unix(&t.socket_name).unwrap();
+    streaming_client
+        .new_rpc(
+            "test.test",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: |mut context: client::rpc::server::Context, _| {
+                    thread::spawn(move || {
+                        context.update(&as_json(r#"{ "msg": "one" }"#)).unwrap();
+                        context.update(&as_json(r#"{ "msg": "two" }"#)).unwrap();
+                        context.update(&as_json(r#"{ "wrong": 1 }"#)).unwrap();
+                        context.finish(rpc::Result::success("done")).unwrap();
+                    });
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client.call("test.test", &as_json("{}")).unwrap();
+
+    let updates: Vec<_> = rpc.updates::<Message>().collect();
+    assert_eq!(3, updates.len());
+    assert_eq!("one", updates[0].as_ref().unwrap().msg);
+    assert_eq!("two", updates[1].as_ref().unwrap().msg);
+    match updates[2] {
+        Err(swiboe::Error::JsonParsing(_)) => (),
+        ref other => panic!("Unexpected update: {:?}", other),
+    }
+    assert_eq!("done", rpc.wait_for::<String>().unwrap());
+}
+
+#[test]
+fn wait_for_failed_rpc() {
+    let t = TestHarness::new();
+
+    let mut callee = client::Client::connect_unix(&t.socket_name).unwrap();
+    callee
+        .new_rpc(
+            "test.failing",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: |mut context: client::rpc::server::Context, _| {
+                    context
+                        .finish(rpc::Result::Err(rpc::Error {
+                            kind: rpc::ErrorKind::InvalidArgs,
+                            details: Some(as_json(r#""broken""#)),
+                        }))
+                        .unwrap();
+                },
+            }),
+        )
+        .unwrap();
+    callee
+        .new_rpc(
+            "test.not_handled",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: |mut context: client::rpc::server::Context, _| {
+                    context.finish(rpc::Result::NotHandled).unwrap();
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client.call("test.failing", &as_json("{}")).unwrap();
+    match rpc.wait_for::<String>() {
+        Err(swiboe::Error::Rpc(err)) => {
+            assert_eq!(rpc::ErrorKind::InvalidArgs, err.kind);
+            assert_eq!(Some(as_json(r#""broken""#)), err.details);
+            assert_eq!(
+                r#"RPC failed: InvalidArgs ("broken")"#,
+                swiboe::Error::Rpc(err).to_string()
+            );
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+
+    let mut rpc = client.call("test.not_handled", &as_json("{}")).unwrap();
+    match rpc.wait_for::<String>() {
+        Err(swiboe::Error::NotHandled) => (),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
 #[test]
 fn call_streaming_rpc_cancelled() {
     let cancelled = sync::Arc::new(sync::Mutex::new(false));
@@ -340,3 +448,1282 @@ fn call_streaming_rpc_cancelled() {
         }
     }
 }
+
+#[test]
+fn call_rpc_with_timeout() {
+    let (cancelled_tx, cancelled_rx) = sync::mpsc::channel();
+    let cancelled_tx = sync::Mutex::new(cancelled_tx);
+
+    let t = TestHarness::new();
+    let mut slow_client = client::Client::connect_unix(&t.socket_name).unwrap();
+    slow_client
+        .new_rpc(
+            "test.test",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: move |mut context: client::rpc::server::Context, _| {
+                    let cancelled = cancelled_tx.lock().unwrap().clone();
+                    thread::spawn(move || {
+                        while !context.cancelled() {
+                            thread::sleep(time::Duration::from_millis(10));
+                        }
+                        cancelled.send(()).unwrap();
+                    });
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client
+        .call_with_timeout(
+            "test.test",
+            &as_json("{}"),
+            time::Duration::from_millis(100),
+        )
+        .unwrap();
+
+    assert_eq!(
+        rpc::Result::Err(rpc::Error {
+            kind: rpc::ErrorKind::Timeout,
+            details: None,
+        }),
+        rpc.wait().unwrap()
+    );
+
+    // Wait for the server thread to end.
+    cancelled_rx
+        .recv_timeout(time::Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn call_rpc_finishing_before_timeout() {
+    let t = TestHarness::new();
+    let mut test_client = client::Client::connect_unix(&t.socket_name).unwrap();
+    test_client
+        .new_rpc(
+            "test.test",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: |mut context: client::rpc::server::Context, _| {
+                    context
+                        .finish(rpc::Result::success(&as_json(r#"{ "foo": "blah" }"#)))
+                        .unwrap();
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client
+        .call_with_timeout(
+            "test.test",
+            &as_json("{}"),
+            time::Duration::from_millis(100),
+        )
+        .unwrap();
+    assert_eq!(
+        rpc::Result::success(as_json(r#"{ "foo": "blah" }"#)),
+        rpc.wait().unwrap()
+    );
+}
+
+#[test]
+fn wait_timeout_on_slow_rpc() {
+    // The RPC finishes once we send something.
+    let (finish_tx, finish_rx) = sync::mpsc::channel();
+    let finish_rx = sync::Arc::new(sync::Mutex::new(finish_rx));
+
+    let t = TestHarness::new();
+    let mut slow_client = client::Client::connect_unix(&t.socket_name).unwrap();
+    slow_client
+        .new_rpc(
+            "test.test",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: move |mut context: client::rpc::server::Context, _| {
+                    let finish_rx = finish_rx.clone();
+                    thread::spawn(move || {
+                        finish_rx.lock().unwrap().recv().unwrap();
+                        context
+                            .finish(rpc::Result::success(&as_json(r#"{ "foo": "blah" }"#)))
+                            .unwrap();
+                    });
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client.call("test.test", &as_json("{}")).unwrap();
+
+    match rpc.wait_timeout(time::Duration::from_millis(10)) {
+        Err(swiboe::Error::Timeout) => (),
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+    match rpc.recv_timeout(time::Duration::from_millis(10)) {
+        Err(swiboe::Error::Timeout) => (),
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+    finish_tx.send(()).unwrap();
+    assert_eq!(
+        rpc::Result::success(as_json(r#"{ "foo": "blah" }"#)),
+        rpc.wait_timeout(time::Duration::from_secs(10)).unwrap()
+    );
+}
+
+// Registers 'test.test' in a new client, which never answers. The receiver gets a message once
+// the call arrived.
+fn connect_hanging_client(t: &TestHarness) -> (client::Client, sync::mpsc::Receiver<()>) {
+    let (called_tx, called_rx) = sync::mpsc::channel();
+    let called_tx = sync::Mutex::new(called_tx);
+    let mut hanging_client = client::Client::connect_unix(&t.socket_name).unwrap();
+    hanging_client
+        .new_rpc(
+            "test.test",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: move |mut context: client::rpc::server::Context, _| {
+                    called_tx.lock().unwrap().send(()).unwrap();
+                    thread::spawn(move || {
+                        // We are cancelled once our client goes away.
+                        while !context.cancelled() {
+                            thread::sleep(time::Duration::from_millis(10));
+                        }
+                    });
+                },
+            }),
+        )
+        .unwrap();
+    (hanging_client, called_rx)
+}
+
+#[test]
+fn callee_disconnects_without_other_handler() {
+    let t = TestHarness::new();
+    let (hanging_client, called) = connect_hanging_client(&t);
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client.call("test.test", &as_json("{}")).unwrap();
+    called.recv_timeout(time::Duration::from_secs(5)).unwrap();
+    drop(hanging_client);
+
+    assert_eq!(
+        rpc::Result::Err(rpc::Error {
+            kind: rpc::ErrorKind::CalleeDisconnected,
+            details: None,
+        }),
+        rpc.wait().unwrap()
+    );
+}
+
+#[test]
+fn callee_disconnects_falls_through_to_next_handler() {
+    let t = TestHarness::new();
+    let (hanging_client, called) = connect_hanging_client(&t);
+
+    let mut fallback_client = client::Client::connect_unix(&t.socket_name).unwrap();
+    fallback_client
+        .new_rpc(
+            "test.test",
+            Box::new(CallbackRpc {
+                priority: 100,
+                callback: |mut context: client::rpc::server::Context, _| {
+                    context
+                        .finish(rpc::Result::success(&as_json(r#"{ "foo": "blah" }"#)))
+                        .unwrap();
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client.call("test.test", &as_json("{}")).unwrap();
+    called.recv_timeout(time::Duration::from_secs(5)).unwrap();
+    drop(hanging_client);
+
+    assert_eq!(
+        rpc::Result::success(as_json(r#"{ "foo": "blah" }"#)),
+        rpc.wait().unwrap()
+    );
+}
+
+#[test]
+fn broadcast_reaches_all_handlers() {
+    let t = TestHarness::new();
+    let (received_tx, received_rx) = sync::mpsc::channel();
+
+    let mut subscribers = Vec::new();
+    for priority in &[50, 100] {
+        let priority = *priority;
+        let received = sync::Mutex::new(received_tx.clone());
+        let mut subscriber = client::Client::connect_unix(&t.socket_name).unwrap();
+        subscriber
+            .new_rpc(
+                "on.test",
+                Box::new(CallbackRpc {
+                    priority: priority,
+                    callback: move |mut context: client::rpc::server::Context,
+                                    args: serde_json::Value| {
+                        received.lock().unwrap().send((priority, args)).unwrap();
+                        // The first handler handles this, but the second one still sees it.
+                        context
+                            .finish(rpc::Result::success(&as_json("{}")))
+                            .unwrap();
+                    },
+                }),
+            )
+            .unwrap();
+        subscribers.push(subscriber);
+    }
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    client
+        .broadcast("on.test", &as_json(r#"{ "foo": "blah" }"#))
+        .unwrap();
+
+    let mut received: Vec<_> = (0..2)
+        .map(|_| {
+            received_rx
+                .recv_timeout(time::Duration::from_secs(5))
+                .unwrap()
+        })
+        .collect();
+    received.sort_by_key(|&(priority, _)| priority);
+    assert_eq!(
+        vec![
+            (50, as_json(r#"{ "foo": "blah" }"#)),
+            (100, as_json(r#"{ "foo": "blah" }"#)),
+        ],
+        received
+    );
+}
+
+struct RpcWithSchema;
+
+impl client::rpc::server::Rpc for RpcWithSchema {
+    fn priority(&self) -> u16 {
+        50
+    }
+
+    fn schema(&self) -> Option<serde_json::Value> {
+        Some(as_json(r#"{ "type": "object" }"#))
+    }
+
+    fn call(&self, mut context: client::rpc::server::Context, _: serde_json::Value) {
+        context.finish(rpc::Result::success(())).unwrap();
+    }
+}
+
+#[test]
+fn list_rpcs_and_clients() {
+    let t = TestHarness::new();
+
+    let mut client1 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client1
+        .new_rpc("test.introspection", Box::new(RpcWithSchema))
+        .unwrap();
+
+    let mut client2 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client2
+        .new_rpc(
+            "test.introspection",
+            Box::new(CallbackRpc {
+                priority: 100,
+                callback: |mut context: client::rpc::server::Context, _| {
+                    context.finish(rpc::Result::NotHandled).unwrap();
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let response: plugin_core::ListRpcsResponse = client
+        .call("core.list_rpcs", &as_json("{}"))
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    let handlers: Vec<_> = response
+        .rpcs
+        .into_iter()
+        .filter(|info| info.name == "test.introspection")
+        .collect();
+    assert_eq!(2, handlers.len());
+    assert_eq!(50, handlers[0].priority);
+    assert_eq!(100, handlers[1].priority);
+    assert!(handlers[0].client != handlers[1].client);
+
+    let response: plugin_core::ListClientsResponse = client
+        .call("core.list_clients", &as_json("{}"))
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    assert!(response.clients.len() >= 3);
+    let ids: Vec<_> = response.clients.iter().map(|info| info.client).collect();
+    assert!(ids.contains(&handlers[0].client));
+    assert!(ids.contains(&handlers[1].client));
+    for info in &response.clients {
+        assert_eq!(std::process::id(), info.pid);
+        assert_eq!(1, info.version);
+        assert!(info.features.contains(&"broadcast".to_string()));
+    }
+}
+
+#[test]
+fn describe_rpc() {
+    let t = TestHarness::new();
+
+    let mut callee = client::Client::connect_unix(&t.socket_name).unwrap();
+    callee
+        .new_rpc("test.introspection", Box::new(RpcWithSchema))
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let response: plugin_core::DescribeRpcResponse = client
+        .call(
+            "core.describe_rpc",
+            &plugin_core::DescribeRpcRequest {
+                name: "test.introspection".into(),
+            },
+        )
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    assert_eq!("test.introspection", response.name);
+    assert_eq!(Some(as_json(r#"{ "type": "object" }"#)), response.schema);
+    assert_eq!(1, response.handlers.len());
+    assert_eq!(50, response.handlers[0].priority);
+
+    let mut rpc = client
+        .call(
+            "core.describe_rpc",
+            &plugin_core::DescribeRpcRequest {
+                name: "test.not_there".into(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc::ErrorKind::UnknownRpc,
+        rpc.wait().unwrap().unwrap_err().kind
+    );
+}
+
+fn answering_rpc(priority: u16, answer: &'static str) -> Box<dyn client::rpc::server::Rpc> {
+    Box::new(CallbackRpc {
+        priority: priority,
+        callback: move |mut context: client::rpc::server::Context, _| {
+            context.finish(rpc::Result::success(answer)).unwrap();
+        },
+    })
+}
+
+#[test]
+fn delete_rpc_falls_through_to_next_handler() {
+    let t = TestHarness::new();
+
+    let mut client1 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client1
+        .new_rpc("test.hot_reload", answering_rpc(50, "one"))
+        .unwrap();
+    let mut client2 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client2
+        .new_rpc("test.hot_reload", answering_rpc(100, "two"))
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let call = |client: &mut client::Client| -> String {
+        client
+            .call("test.hot_reload", &as_json("{}"))
+            .unwrap()
+            .wait_for()
+            .unwrap()
+    };
+    assert_eq!("one", call(&mut client));
+
+    client1.delete_rpc("test.hot_reload").unwrap();
+    assert_eq!("two", call(&mut client));
+
+    // Registering again after deleting works, e.g. for reloading a plugin.
+    client1
+        .new_rpc("test.hot_reload", answering_rpc(50, "three"))
+        .unwrap();
+    assert_eq!("three", call(&mut client));
+
+    client1.delete_rpc("test.hot_reload").unwrap();
+    client2.delete_rpc("test.hot_reload").unwrap();
+    let mut rpc = client.call("test.hot_reload", &as_json("{}")).unwrap();
+    assert_eq!(
+        rpc::ErrorKind::UnknownRpc,
+        rpc.wait().unwrap().unwrap_err().kind
+    );
+}
+
+#[test]
+fn delete_rpc_not_registered_by_client() {
+    let t = TestHarness::new();
+
+    let mut callee = client::Client::connect_unix(&t.socket_name).unwrap();
+    callee
+        .new_rpc("test.hot_reload", answering_rpc(50, "one"))
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    for name in &["test.hot_reload", "test.not_there"] {
+        assert_rpc_error(rpc::ErrorKind::UnknownRpc, client.delete_rpc(name));
+    }
+
+    // The registration of the callee is untouched.
+    let answer: String = client
+        .call("test.hot_reload", &as_json("{}"))
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    assert_eq!("one", answer);
+}
+
+// Sends the core call 'function' for 'test.pipelined' without waiting for earlier calls.
+fn write_core_call(stream: &mut UnixStream, function: &str, context: &str) {
+    let call = format!(
+        r#"{{ "RpcCall": {{
+            "function": "{}",
+            "context": "{}",
+            "args": {{ "name": "test.pipelined", "priority": 50 }}
+        }} }}"#,
+        function, context
+    );
+    write_frame(stream, call.as_bytes());
+}
+
+fn read_result(stream: &mut UnixStream) -> serde_json::Value {
+    let response: serde_json::Value = serde_json::from_slice(&read_frame(stream)).unwrap();
+    response["RpcResponse"]["kind"]["Last"].clone()
+}
+
+#[test]
+fn pipelined_registrations_see_each_other() {
+    let t = TestHarness::new();
+    let mut stream = UnixStream::connect(&t.socket_name).unwrap();
+    raw_handshake(&mut stream, "Json");
+
+    write_core_call(&mut stream, "core.new_rpc", "new1");
+    write_core_call(&mut stream, "core.new_rpc", "new2");
+    write_core_call(&mut stream, "core.delete_rpc", "delete1");
+    write_core_call(&mut stream, "core.delete_rpc", "delete2");
+
+    assert!(read_result(&mut stream).get("Ok").is_some());
+    let error = read_result(&mut stream)["Err"].clone();
+    assert_eq!(as_json(r#""InvalidArgs""#), error["kind"]);
+    assert_eq!(as_json(r#""already_registered""#), error["details"]);
+    assert!(read_result(&mut stream).get("Ok").is_some());
+    let error = read_result(&mut stream)["Err"].clone();
+    assert_eq!(as_json(r#""UnknownRpc""#), error["kind"]);
+}
+
+fn assert_rpc_error(kind: rpc::ErrorKind, result: swiboe::Result<()>) {
+    match result {
+        Err(swiboe::Error::Rpc(err)) => assert_eq!(kind, err.kind),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn malformed_core_calls_return_errors() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    let mut rpc = client
+        .call("core.new_rpc", &as_json(r#"{ "blub": 12 }"#))
+        .unwrap();
+    assert_eq!(
+        rpc::ErrorKind::InvalidArgs,
+        rpc.wait().unwrap().unwrap_err().kind
+    );
+
+    let mut rpc = client.call("core.not_there", &as_json("{}")).unwrap();
+    assert_eq!(
+        rpc::ErrorKind::UnknownRpc,
+        rpc.wait().unwrap().unwrap_err().kind
+    );
+
+    // The server is still alive.
+    client
+        .new_rpc("test.still_alive", answering_rpc(50, "yes"))
+        .unwrap();
+    let answer: String = client
+        .call("test.still_alive", &as_json("{}"))
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    assert_eq!("yes", answer);
+}
+
+#[test]
+fn new_rpc_with_core_prefix() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    assert_rpc_error(
+        rpc::ErrorKind::InvalidArgs,
+        client.new_rpc("core.exit", answering_rpc(0, "hijacked")),
+    );
+    let rpcs: plugin_core::ListRpcsResponse = client
+        .call("core.list_rpcs", &as_json("{}"))
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    assert!(rpcs.rpcs.iter().all(|info| info.name != "core.exit"));
+}
+
+#[test]
+fn new_rpc_twice_by_same_client() {
+    let t = TestHarness::new();
+
+    let mut client1 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client1
+        .new_rpc("test.twice", answering_rpc(50, "one"))
+        .unwrap();
+    assert_rpc_error(
+        rpc::ErrorKind::InvalidArgs,
+        client1.new_rpc("test.twice", answering_rpc(10, "two")),
+    );
+
+    // Other clients can still add handlers.
+    let mut client2 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client2
+        .new_rpc("test.twice", answering_rpc(100, "three"))
+        .unwrap();
+
+    // The rejected registration did not replace the first one.
+    let answer: String = client2
+        .call("test.twice", &as_json("{}"))
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    assert_eq!("one", answer);
+}
+
+#[test]
+fn new_rpc_is_called_right_after_registration() {
+    let t = TestHarness::new();
+    let mut client1 = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    for index in 0..20 {
+        let name = format!("test.race{}", index);
+        // Keeps calling until the function exists. The first call that reaches it must be
+        // handled, even if the registration is still on its way back to 'client1'.
+        let caller = {
+            let socket_name = t.socket_name.clone();
+            let name = name.clone();
+            thread::spawn(move || {
+                let mut client2 = client::Client::connect_unix(&socket_name).unwrap();
+                loop {
+                    match client2.call(&name, &as_json("{}")).unwrap().wait().unwrap() {
+                        rpc::Result::Err(ref err) if err.kind == rpc::ErrorKind::UnknownRpc => (),
+                        result => return result,
+                    }
+                }
+            })
+        };
+        client1.new_rpc(&name, answering_rpc(50, "here")).unwrap();
+        assert_eq!(
+            rpc::Result::success("here"),
+            caller.join().unwrap(),
+            "{}",
+            name
+        );
+    }
+}
+
+// A handler that answers NotHandled, but only after it was told to. Lets tests change the
+// registered handlers while an RPC is running.
+fn blocking_not_handled_rpc(
+    priority: u16,
+) -> (
+    Box<dyn client::rpc::server::Rpc>,
+    sync::mpsc::Receiver<()>,
+    sync::mpsc::Sender<()>,
+) {
+    let (called_tx, called_rx) = sync::mpsc::channel();
+    let (go_tx, go_rx) = sync::mpsc::channel();
+    let called_tx = sync::Mutex::new(called_tx);
+    let go_rx = sync::Mutex::new(go_rx);
+    let rpc = Box::new(CallbackRpc {
+        priority: priority,
+        callback: move |mut context: client::rpc::server::Context, _| {
+            called_tx.lock().unwrap().send(()).unwrap();
+            go_rx.lock().unwrap().recv().unwrap();
+            context.finish(rpc::Result::NotHandled).unwrap();
+        },
+    });
+    (rpc, called_rx, go_tx)
+}
+
+#[test]
+fn handler_registered_during_call_is_not_tried() {
+    let t = TestHarness::new();
+
+    let (blocking_rpc, called, go) = blocking_not_handled_rpc(10);
+    let mut client1 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client1.new_rpc("test.chain", blocking_rpc).unwrap();
+    let mut client2 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client2
+        .new_rpc("test.chain", answering_rpc(30, "old"))
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client.call("test.chain", &as_json("{}")).unwrap();
+    called.recv().unwrap();
+
+    let mut client3 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client3
+        .new_rpc("test.chain", answering_rpc(20, "new"))
+        .unwrap();
+    go.send(()).unwrap();
+    assert_eq!("old", rpc.wait_for::<String>().unwrap());
+
+    // Later calls see the new handler.
+    let mut rpc = client.call("test.chain", &as_json("{}")).unwrap();
+    called.recv().unwrap();
+    go.send(()).unwrap();
+    assert_eq!("new", rpc.wait_for::<String>().unwrap());
+}
+
+#[test]
+fn handlers_deleted_during_call_are_skipped() {
+    let t = TestHarness::new();
+
+    let (blocking_rpc, called, go) = blocking_not_handled_rpc(10);
+    let mut client1 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client1.new_rpc("test.chain", blocking_rpc).unwrap();
+    let mut client2 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client2
+        .new_rpc("test.chain", answering_rpc(20, "two"))
+        .unwrap();
+    let mut client3 = client::Client::connect_unix(&t.socket_name).unwrap();
+    client3
+        .new_rpc("test.chain", answering_rpc(30, "three"))
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client.call("test.chain", &as_json("{}")).unwrap();
+    called.recv().unwrap();
+
+    // Deleting the running handler itself must not lose our place in the chain.
+    client1.delete_rpc("test.chain").unwrap();
+    client2.delete_rpc("test.chain").unwrap();
+    go.send(()).unwrap();
+    assert_eq!("three", rpc.wait_for::<String>().unwrap());
+}
+
+// Connects to the server without a client and sends 'message' as it would go over the wire.
+fn send_raw_message(t: &TestHarness, message: &serde_json::Value) -> UnixStream {
+    let mut stream = UnixStream::connect(&t.socket_name).unwrap();
+    raw_handshake(&mut stream, "Json");
+    write_frame(&mut stream, &serde_json::to_vec(message).unwrap());
+    stream
+}
+
+fn write_frame<W: Write>(stream: &mut W, data: &[u8]) {
+    let len = data.len() as u32;
+    stream
+        .write_all(&[
+            len as u8,
+            (len >> 8) as u8,
+            (len >> 16) as u8,
+            (len >> 24) as u8,
+        ])
+        .unwrap();
+    stream.write_all(data).unwrap();
+}
+
+fn read_frame<R: Read>(stream: &mut R) -> Vec<u8> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let len = (len[0] as usize)
+        | ((len[1] as usize) << 8)
+        | ((len[2] as usize) << 16)
+        | ((len[3] as usize) << 24);
+    let mut data = vec![0; len];
+    stream.read_exact(&mut data).unwrap();
+    data
+}
+
+fn raw_hello(version: u32, encoding: &str) -> String {
+    format!(
+        r#"{{ "Hello": {{
+            "version": {},
+            "name": "raw",
+            "pid": 0,
+            "features": [],
+            "encodings": ["{}"]
+        }} }}"#,
+        version, encoding
+    )
+}
+
+// Says hello as a client that only speaks 'encoding' and returns the encoding the server picked.
+fn raw_handshake(stream: &mut UnixStream, encoding: &str) -> serde_json::Value {
+    write_frame(stream, raw_hello(1, encoding).as_bytes());
+    let welcome: serde_json::Value = serde_json::from_slice(&read_frame(stream)).unwrap();
+    welcome["Welcome"]["encoding"].clone()
+}
+
+fn list_clients_call() -> serde_json::Value {
+    as_json(
+        r#"{ "RpcCall": {
+            "function": "core.list_clients",
+            "context": "raw",
+            "args": {}
+        } }"#,
+    )
+}
+
+#[test]
+fn negotiate_json_encoding() {
+    let t = TestHarness::new();
+    let mut stream = UnixStream::connect(&t.socket_name).unwrap();
+    assert_eq!(as_json(r#""Json""#), raw_handshake(&mut stream, "Json"));
+
+    write_frame(
+        &mut stream,
+        &serde_json::to_vec(&list_clients_call()).unwrap(),
+    );
+    let response: serde_json::Value = serde_json::from_slice(&read_frame(&mut stream)).unwrap();
+    assert_eq!(as_json(r#""raw""#), response["RpcResponse"]["context"]);
+    assert!(response["RpcResponse"]["kind"]["Last"]["Ok"]["clients"].is_array());
+}
+
+#[test]
+fn negotiate_message_pack_encoding() {
+    let t = TestHarness::new();
+    let mut stream = UnixStream::connect(&t.socket_name).unwrap();
+    assert_eq!(
+        as_json(r#""MessagePack""#),
+        raw_handshake(&mut stream, "MessagePack")
+    );
+
+    write_frame(
+        &mut stream,
+        &rmp_serde::to_vec_named(&list_clients_call()).unwrap(),
+    );
+    let response: serde_json::Value = rmp_serde::from_slice(&read_frame(&mut stream)).unwrap();
+    assert_eq!(as_json(r#""raw""#), response["RpcResponse"]["context"]);
+    assert!(response["RpcResponse"]["kind"]["Last"]["Ok"]["clients"].is_array());
+}
+
+#[test]
+fn hello_with_wrong_version_is_refused() {
+    let t = TestHarness::new();
+    let mut stream = UnixStream::connect(&t.socket_name).unwrap();
+    write_frame(&mut stream, raw_hello(0, "Json").as_bytes());
+    let refused: serde_json::Value = serde_json::from_slice(&read_frame(&mut stream)).unwrap();
+    assert_eq!(as_json("1"), refused["Refused"]["version"]);
+    assert_disconnected(&mut stream);
+}
+
+#[test]
+fn call_before_hello_is_refused() {
+    let t = TestHarness::new();
+    let mut stream = UnixStream::connect(&t.socket_name).unwrap();
+
+    // Without a successful hello the server does not run any calls for us.
+    write_frame(
+        &mut stream,
+        &serde_json::to_vec(&list_clients_call()).unwrap(),
+    );
+    let response: serde_json::Value = serde_json::from_slice(&read_frame(&mut stream)).unwrap();
+    let error = &response["RpcResponse"]["kind"]["Last"]["Err"];
+    assert_eq!(as_json(r#""hello_required""#), error["details"]);
+}
+
+// Waits until the server closes 'stream' on its end.
+fn assert_disconnected(stream: &mut UnixStream) {
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).unwrap();
+}
+
+#[test]
+fn calls_right_after_hello_know_the_client() {
+    let t = TestHarness::new();
+    let mut stream = UnixStream::connect(&t.socket_name).unwrap();
+    write_frame(&mut stream, raw_hello(1, "Json").as_bytes());
+    write_frame(
+        &mut stream,
+        &serde_json::to_vec(&list_clients_call()).unwrap(),
+    );
+
+    let welcome: serde_json::Value = serde_json::from_slice(&read_frame(&mut stream)).unwrap();
+    assert!(welcome.get("Welcome").is_some());
+    let clients = read_result(&mut stream)["Ok"]["clients"].clone();
+    assert!(clients
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|client| client["name"] == "raw"));
+}
+
+#[test]
+fn disconnect_right_after_hello_removes_registrations() {
+    let t = TestHarness::new();
+    let mut stream = UnixStream::connect(&t.socket_name).unwrap();
+    write_frame(&mut stream, raw_hello(1, "Json").as_bytes());
+    write_core_call(&mut stream, "core.new_rpc", "new");
+    // A frame that is too big, so that the server drops us.
+    stream.write_all(&[0xff, 0xff, 0xff, 0xff, b'{']).unwrap();
+    assert_disconnected(&mut stream);
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let response: plugin_core::ListRpcsResponse = client
+        .call("core.list_rpcs", &as_json("{}"))
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    assert!(response.rpcs.iter().all(|rpc| rpc.name != "test.pipelined"));
+}
+
+#[test]
+fn invalid_frames_disconnect_client() {
+    let t = TestHarness::new();
+
+    let mut oversized = UnixStream::connect(&t.socket_name).unwrap();
+    raw_handshake(&mut oversized, "Json");
+    oversized
+        .write_all(&[0xff, 0xff, 0xff, 0xff, b'{'])
+        .unwrap();
+    assert_disconnected(&mut oversized);
+
+    let mut garbled = UnixStream::connect(&t.socket_name).unwrap();
+    raw_handshake(&mut garbled, "Json");
+    write_frame(&mut garbled, b"{ \"RpcCall\": ");
+    assert_disconnected(&mut garbled);
+
+    // The server is still fine and forgot about both of them.
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let response: plugin_core::ListClientsResponse = client
+        .call("core.list_clients", &as_json("{}"))
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    assert!(response.clients.iter().all(|info| info.name != "raw"));
+}
+
+#[test]
+fn oversized_answer_fails_calls_and_drops_connection() {
+    let t = TestHarness::new();
+    let mut callee = client::Client::connect_unix(&t.socket_name).unwrap();
+    callee
+        .new_rpc(
+            "test.big",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: |mut context: client::rpc::server::Context, _| {
+                    let answer: String = (0..2048).map(|_| 'x').collect();
+                    context.finish(rpc::Result::success(answer)).unwrap();
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client =
+        client::Client::connect_unix_with_max_frame_size(&t.socket_name, 1024).unwrap();
+    for _ in 0..2 {
+        let result = client
+            .call("test.big", &as_json("{}"))
+            .unwrap()
+            .wait_for::<String>();
+        match result {
+            Err(swiboe::Error::Rpc(err)) => assert_eq!(rpc::ErrorKind::Io, err.kind),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn client_knows_server_features() {
+    let t = TestHarness::new();
+    let client = client::Client::connect_unix(&t.socket_name).unwrap();
+    assert!(client
+        .server_features()
+        .iter()
+        .any(|feature| feature == "broadcast"));
+}
+
+#[test]
+fn only_caller_can_cancel() {
+    let t = TestHarness::new();
+    let (context_tx, context_rx) = sync::mpsc::channel();
+    let context_tx = sync::Mutex::new(context_tx);
+
+    let mut callee = client::Client::connect_unix(&t.socket_name).unwrap();
+    callee
+        .new_rpc(
+            "test.cancel",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: move |context: client::rpc::server::Context, _| {
+                    context_tx.lock().unwrap().send(context).unwrap();
+                },
+            }),
+        )
+        .unwrap();
+    callee
+        .new_rpc("test.ping", answering_rpc(50, "pong"))
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let mut rpc = client.call("test.cancel", &as_json("{}")).unwrap();
+    let mut context = context_rx
+        .recv_timeout(time::Duration::from_secs(5))
+        .unwrap();
+
+    let mut stream = send_raw_message(
+        &t,
+        &as_json(&format!(
+            r#"{{ "RpcCancel": {{ "context": "{}" }} }}"#,
+            context.context()
+        )),
+    );
+    // The server and the callee handle messages in order, so a cancel that was passed on wrongly
+    // has arrived once the ping is answered.
+    write_frame(
+        &mut stream,
+        &serde_json::to_vec(&as_json(
+            r#"{ "RpcCall": { "function": "test.ping", "context": "ping", "args": {} } }"#,
+        ))
+        .unwrap(),
+    );
+    let response: serde_json::Value = serde_json::from_slice(&read_frame(&mut stream)).unwrap();
+    assert_eq!(
+        as_json(r#""pong""#),
+        response["RpcResponse"]["kind"]["Last"]["Ok"]
+    );
+
+    assert!(!context.cancelled());
+    context.finish(rpc::Result::success("done")).unwrap();
+    assert_eq!("done", rpc.wait_for::<String>().unwrap());
+}
+
+#[test]
+fn cancel_propagates_to_nested_calls() {
+    let t = TestHarness::new();
+
+    let (inner_called_tx, inner_called_rx) = sync::mpsc::channel();
+    let inner_called_tx = sync::Mutex::new(inner_called_tx);
+    let (inner_cancelled_tx, inner_cancelled_rx) = sync::mpsc::channel();
+    let inner_cancelled_tx = sync::Mutex::new(inner_cancelled_tx);
+    let mut inner_callee = client::Client::connect_unix(&t.socket_name).unwrap();
+    inner_callee
+        .new_rpc(
+            "test.inner",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: move |mut context: client::rpc::server::Context, _| {
+                    inner_called_tx.lock().unwrap().send(()).unwrap();
+                    let inner_cancelled = inner_cancelled_tx.lock().unwrap().clone();
+                    thread::spawn(move || {
+                        while !context.cancelled() {
+                            thread::sleep(time::Duration::from_millis(10));
+                        }
+                        inner_cancelled.send(()).unwrap();
+                    });
+                },
+            }),
+        )
+        .unwrap();
+
+    let (inner_result_tx, inner_result_rx) = sync::mpsc::channel();
+    let inner_result_tx = sync::Mutex::new(inner_result_tx);
+    let mut outer_callee = client::Client::connect_unix(&t.socket_name).unwrap();
+    outer_callee
+        .new_rpc(
+            "test.outer",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: move |mut context: client::rpc::server::Context, _| {
+                    let inner_result_tx = inner_result_tx.lock().unwrap().clone();
+                    thread::spawn(move || {
+                        let mut inner = context.call("test.inner", &as_json("{}")).unwrap();
+                        inner_result_tx.send(inner.wait().unwrap()).unwrap();
+                        assert!(context.cancelled());
+                    });
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let rpc = client.call("test.outer", &as_json("{}")).unwrap();
+    inner_called_rx
+        .recv_timeout(time::Duration::from_secs(5))
+        .unwrap();
+    rpc.cancel().unwrap();
+
+    assert_eq!(
+        rpc::Result::Err(rpc::Error {
+            kind: rpc::ErrorKind::Cancelled,
+            details: None,
+        }),
+        inner_result_rx.recv().unwrap()
+    );
+    inner_cancelled_rx
+        .recv_timeout(time::Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn dropping_rpc_cancels_it() {
+    let t = TestHarness::new();
+    let (called_tx, called_rx) = sync::mpsc::channel();
+    let called_tx = sync::Mutex::new(called_tx);
+    let (cancelled_tx, cancelled_rx) = sync::mpsc::channel();
+    let cancelled_tx = sync::Mutex::new(cancelled_tx);
+
+    let mut callee = client::Client::connect_unix(&t.socket_name).unwrap();
+    callee
+        .new_rpc(
+            "test.drop",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: move |mut context: client::rpc::server::Context, _| {
+                    called_tx.lock().unwrap().send(()).unwrap();
+                    let cancelled = cancelled_tx.lock().unwrap().clone();
+                    thread::spawn(move || {
+                        while !context.cancelled() {
+                            thread::sleep(time::Duration::from_millis(10));
+                        }
+                        cancelled.send(()).unwrap();
+                    });
+                },
+            }),
+        )
+        .unwrap();
+
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    {
+        let _rpc = client.call("test.drop", &as_json("{}")).unwrap();
+        called_rx
+            .recv_timeout(time::Duration::from_secs(5))
+            .unwrap();
+    }
+    cancelled_rx
+        .recv_timeout(time::Duration::from_secs(5))
+        .unwrap();
+}
+
+// Finds a port on localhost that nobody listens on right now.
+fn free_tcp_address() -> net::SocketAddr {
+    net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn assert_refused(result: swiboe::Result<client::Client>) {
+    match result {
+        Err(swiboe::Error::Refused(_)) => (),
+        Err(err) => panic!("Expected Refused, got {:?}", err),
+        Ok(_) => panic!("Expected Refused, got a client"),
+    }
+}
+
+#[test]
+fn tcp_clients_need_auth_token() {
+    let socket_name = temporary_socket_name();
+    let mut token_file = env::temp_dir();
+    token_file.push(format!("{}.token", Uuid::new_v4().to_string()));
+    fs::File::create(&token_file)
+        .unwrap()
+        .write_all(b"sw0rdfish\n")
+        .unwrap();
+    let config = Config {
+        auth_token: Some(ipc::read_auth_token(&token_file).unwrap()),
+        ..Config::default()
+    };
+    fs::remove_file(&token_file).unwrap();
+
+    let address = free_tcp_address();
+    let mut server =
+        Server::launch_with_config(&socket_name, &[&address.to_string()], config).unwrap();
+
+    assert_refused(client::Client::connect_tcp(&address));
+    assert_refused(client::Client::connect_tcp_with_auth_token(
+        &address,
+        "swordfish",
+    ));
+
+    let mut client = client::Client::connect_tcp_with_auth_token(&address, "sw0rdfish").unwrap();
+    let response: plugin_core::ListClientsResponse = client
+        .call("core.list_clients", &as_json("{}"))
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    // The refused clients never made it to the server, we only see the plugins and us.
+    assert_eq!(4, response.clients.len());
+
+    // The unix domain socket is only reachable locally and needs no token.
+    let _local_client = client::Client::connect_unix(&socket_name).unwrap();
+
+    drop(client);
+    server.shutdown();
+}
+
+fn hello_with_auth_token(auth_token: &str) -> Vec<u8> {
+    let mut hello = as_json(&raw_hello(1, "Json"));
+    hello["Hello"]["auth_token"] = serde_json::Value::String(auth_token.into());
+    serde_json::to_vec(&hello).unwrap()
+}
+
+#[test]
+fn wrong_auth_token_disconnects_client() {
+    let socket_name = temporary_socket_name();
+    let config = Config {
+        auth_token: Some("sw0rdfish".into()),
+        ..Config::default()
+    };
+    let address = free_tcp_address();
+    let mut server =
+        Server::launch_with_config(&socket_name, &[&address.to_string()], config).unwrap();
+
+    let mut stream = net::TcpStream::connect(&address).unwrap();
+    stream
+        .set_read_timeout(Some(time::Duration::from_secs(5)))
+        .unwrap();
+    write_frame(&mut stream, &hello_with_auth_token("swordfish"));
+    let refused: serde_json::Value = serde_json::from_slice(&read_frame(&mut stream)).unwrap();
+    assert!(refused.get("Refused").is_some());
+
+    // Guessing on is not possible, the server does not listen anymore. Writing might fail if the
+    // connection is already gone.
+    let mut hello = Vec::new();
+    write_frame(&mut hello, &hello_with_auth_token("sw0rdfish"));
+    let _ = stream.write_all(&hello);
+    let mut data = Vec::new();
+    match stream.read_to_end(&mut data) {
+        Ok(_) => assert!(data.is_empty()),
+        Err(err) => assert_eq!(io::ErrorKind::ConnectionReset, err.kind()),
+    }
+
+    server.shutdown();
+}
+
+#[test]
+fn empty_auth_token_file() {
+    let mut token_file = env::temp_dir();
+    token_file.push(format!("{}.token", Uuid::new_v4().to_string()));
+    fs::File::create(&token_file)
+        .unwrap()
+        .write_all(b" \n")
+        .unwrap();
+    assert!(ipc::read_auth_token(&token_file).is_err());
+    fs::remove_file(&token_file).unwrap();
+}
+
+struct Certificates {
+    ca_file: path::PathBuf,
+    certificate_file: path::PathBuf,
+    private_key_file: path::PathBuf,
+}
+
+// Creates a CA and a certificate for 'localhost' signed by it in 'dir'.
+fn create_certificates(dir: &path::Path) -> Certificates {
+    let ca_key = rcgen::KeyPair::generate().unwrap();
+    let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let key = rcgen::KeyPair::generate().unwrap();
+    let certificate = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&key, &ca, &ca_key)
+        .unwrap();
+
+    let certificates = Certificates {
+        ca_file: dir.join("ca.pem"),
+        certificate_file: dir.join("certificate.pem"),
+        private_key_file: dir.join("private_key.pem"),
+    };
+    fs::write(&certificates.ca_file, ca.pem()).unwrap();
+    fs::write(&certificates.certificate_file, certificate.pem()).unwrap();
+    fs::write(&certificates.private_key_file, key.serialize_pem()).unwrap();
+    certificates
+}
+
+fn launch_tls_server(
+    socket_name: &path::Path,
+    certificates: &Certificates,
+) -> (Server, net::SocketAddr) {
+    let config = Config {
+        tls: Some(
+            tls::server_config(
+                &certificates.certificate_file,
+                &certificates.private_key_file,
+            )
+            .unwrap(),
+        ),
+        ..Config::default()
+    };
+    let address = free_tcp_address();
+    let server = Server::launch_with_config(socket_name, &[&address.to_string()], config).unwrap();
+    (server, address)
+}
+
+#[test]
+fn tls_connection() {
+    let temp_directory = TempDir::new("swiboe").unwrap();
+    let certificates = create_certificates(temp_directory.path());
+    let socket_name = temporary_socket_name();
+    let (mut server, address) = launch_tls_server(&socket_name, &certificates);
+
+    let mut callee = client::Client::connect_unix(&socket_name).unwrap();
+    callee
+        .new_rpc(
+            "test.echo",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: |mut context: client::rpc::server::Context, args| {
+                    context.finish(rpc::Result::Ok(args)).unwrap();
+                },
+            }),
+        )
+        .unwrap();
+
+    let tls_config = tls::client_config(&certificates.ca_file).unwrap();
+    let mut client = client::Client::connect_tls(&address, "localhost", tls_config, None).unwrap();
+    // Bigger than anything that fits into one TLS record or socket buffer.
+    let message = "x".repeat(1024 * 1024);
+    let echo: String = client
+        .call("test.echo", &message)
+        .unwrap()
+        .wait_for()
+        .unwrap();
+    assert_eq!(message, echo);
+
+    drop(client);
+    drop(callee);
+    server.shutdown();
+}
+
+#[test]
+fn tls_needs_trusted_certificate() {
+    let temp_directory = TempDir::new("swiboe").unwrap();
+    let certificates = create_certificates(temp_directory.path());
+    let socket_name = temporary_socket_name();
+    let (mut server, address) = launch_tls_server(&socket_name, &certificates);
+
+    // A CA that did not sign the certificate of the server.
+    let other_dir = temp_directory.path().join("other");
+    fs::create_dir(&other_dir).unwrap();
+    let other_certificates = create_certificates(&other_dir);
+    let tls_config = tls::client_config(&other_certificates.ca_file).unwrap();
+    assert!(client::Client::connect_tls(&address, "localhost", tls_config, None).is_err());
+
+    // The certificate is not for this name.
+    let tls_config = tls::client_config(&certificates.ca_file).unwrap();
+    assert!(client::Client::connect_tls(&address, "example.com", tls_config, None).is_err());
+
+    // The server does not talk to clients without TLS.
+    assert!(client::Client::connect_tcp(&address).is_err());
+
+    server.shutdown();
+}
@@ -0,0 +1,169 @@
+// Copyright (c) The Swiboe development team. All rights reserved.
+// Licensed under the Apache License, Version 2.0. See LICENSE.txt
+// in the project root for license information.
+
+use std::fmt::Debug;
+use std::io::Cursor;
+use swiboe::ipc;
+use swiboe::rpc;
+use swiboe::{Error, Result};
+
+// A xorshift generator, so that failures of the fuzz tests can be reproduced.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+        (self.next() % max as u64) as usize
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    fn encoding(&mut self) -> ipc::Encoding {
+        if self.next() & 1 == 0 {
+            ipc::Encoding::Json
+        } else {
+            ipc::Encoding::MessagePack
+        }
+    }
+}
+
+fn frame(len: u32, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![
+        len as u8,
+        (len >> 8) as u8,
+        (len >> 16) as u8,
+        (len >> 24) as u8,
+    ];
+    frame.extend_from_slice(data);
+    frame
+}
+
+fn reader(data: Vec<u8>, encoding: ipc::Encoding) -> ipc::Reader<Cursor<Vec<u8>>> {
+    let mut reader = ipc::Reader::new(Cursor::new(data));
+    reader.set_encoding(encoding);
+    reader
+}
+
+fn assert_invalid_frame<T: Debug>(result: Result<T>) {
+    match result {
+        Err(Error::InvalidFrame(_)) => (),
+        other => panic!("Expected InvalidFrame, got {:?}", other),
+    }
+}
+
+fn cancel_message() -> ipc::Message {
+    ipc::Message::RpcCancel(rpc::Cancel {
+        context: "context".into(),
+    })
+}
+
+#[test]
+fn read_written_messages() {
+    for encoding in &[ipc::Encoding::Json, ipc::Encoding::MessagePack] {
+        let mut writer = ipc::Writer::new(Vec::new());
+        writer.set_encoding(*encoding);
+        writer.write_message(&cancel_message()).unwrap();
+        writer.write_message(&cancel_message()).unwrap();
+
+        let mut reader = reader(writer.socket.clone(), *encoding);
+        match reader.read_message().unwrap() {
+            ipc::Message::RpcCancel(cancel) => assert_eq!("context", cancel.context),
+            other => panic!("Unexpected message {:?}", other),
+        }
+
+        let mut reader = self::reader(writer.socket, *encoding);
+        assert!(reader.try_read_message().unwrap().is_some());
+        assert!(reader.try_read_message().unwrap().is_some());
+        assert!(reader.try_read_message().unwrap().is_none());
+    }
+}
+
+#[test]
+fn oversized_frame_is_rejected() {
+    let data = frame(1025, &[0; 1025]);
+
+    let mut reader = reader(data.clone(), ipc::Encoding::Json);
+    reader.set_max_frame_size(1024);
+    assert_invalid_frame(reader.read_message());
+
+    let mut reader = self::reader(data, ipc::Encoding::Json);
+    reader.set_max_frame_size(1024);
+    assert_invalid_frame(reader.try_read_message());
+}
+
+#[test]
+fn huge_frame_length_is_rejected_before_reading_the_frame() {
+    let data = frame(u32::MAX, b"{}");
+    assert_invalid_frame(reader(data.clone(), ipc::Encoding::Json).read_message());
+    assert_invalid_frame(reader(data, ipc::Encoding::Json).try_read_message());
+}
+
+#[test]
+fn garbled_frame_is_rejected() {
+    for encoding in &[ipc::Encoding::Json, ipc::Encoding::MessagePack] {
+        let data = frame(5, b"\xc1{]\"\xff");
+        assert_invalid_frame(reader(data.clone(), *encoding).read_message());
+        assert_invalid_frame(reader(data, *encoding).try_read_message());
+    }
+}
+
+#[test]
+fn truncated_frame() {
+    let data = frame(10, b"{}");
+    match reader(data.clone(), ipc::Encoding::Json).read_message() {
+        Err(Error::Io(_)) => (),
+        other => panic!("Expected Io, got {:?}", other),
+    }
+    assert!(reader(data, ipc::Encoding::Json)
+        .try_read_message()
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn fuzz_reader_with_random_bytes() {
+    let mut random = Random(0x2545F4914F6CDD1D);
+    for _ in 0..2000 {
+        let len = random.below(2048);
+        let data = random.bytes(len);
+        let encoding = random.encoding();
+
+        // Nothing of this may panic, whatever the result is.
+        let mut reader = reader(data.clone(), encoding);
+        reader.set_max_frame_size(1024);
+        while reader.read_message().is_ok() {}
+
+        let mut reader = self::reader(data, encoding);
+        reader.set_max_frame_size(1024);
+        while let Ok(Some(_)) = reader.try_read_message() {}
+    }
+}
+
+#[test]
+fn fuzz_reader_with_random_frames() {
+    let mut random = Random(0x9E3779B97F4A7C15);
+    for _ in 0..2000 {
+        let len = random.below(512);
+        let data = frame(len as u32, &random.bytes(len));
+        let encoding = random.encoding();
+
+        // The length is right, so the frame is either a message or invalid.
+        match reader(data.clone(), encoding).read_message() {
+            Ok(_) | Err(Error::InvalidFrame(_)) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match reader(data, encoding).try_read_message() {
+            Ok(Some(_)) | Err(Error::InvalidFrame(_)) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
@@ -2,12 +2,22 @@
 // Licensed under the Apache License, Version 2.0. See LICENSE.txt
 // in the project root for license information.
 
-use std::sync::{Arc, Mutex};
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time;
+use swiboe;
 use swiboe::client;
 use swiboe::client::RpcCaller;
 use swiboe::plugin::buffer;
 use swiboe::rpc;
+use swiboe::server::Config;
 use swiboe::testing::TestHarness;
 use {create_file, CallbackRpc};
 
@@ -21,9 +31,47 @@ fn wait_for_true_with_timeout(mutex: &Mutex<bool>) -> bool {
     false
 }
 
+// Passes the arguments of every call of the broadcast 'function' on.
+fn subscribe<T: DeserializeOwned + Send + 'static>(
+    client: &mut client::Client,
+    function: &str,
+) -> mpsc::Receiver<T> {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    client
+        .new_rpc(
+            function,
+            Box::new(CallbackRpc {
+                priority: 100,
+                callback: move |mut sender: client::rpc::server::Context, args| {
+                    sender.finish(rpc::Result::success(())).unwrap();
+                    let event: T = serde_json::from_value(args).unwrap();
+                    // The test might be done already.
+                    let _ = tx.lock().unwrap().send(event);
+                },
+            }),
+        )
+        .unwrap();
+    rx
+}
+
+fn next_event<T>(events: &mpsc::Receiver<T>) -> T {
+    events.recv_timeout(time::Duration::from_secs(5)).unwrap()
+}
+
+fn read_file(path: &path::Path) -> String {
+    let mut content = String::new();
+    fs::File::open(path)
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    content
+}
+
 fn create_buffer(client: &mut client::Client, expected_index: usize, content: Option<&str>) {
     let request = buffer::new::Request {
         content: content.map(|s| s.to_string()),
+        uri: None,
     };
     let mut rpc = client.call("buffer.new", &request).unwrap();
     assert_eq!(
@@ -77,6 +125,7 @@ fn buffer_new_with_content() {
         rpc.wait().unwrap(),
         rpc::Result::success(buffer::get_content::Response {
             content: content.into(),
+            revision: 0,
         })
     );
 }
@@ -129,6 +178,7 @@ fn buffer_open_file() {
         rpc.wait().unwrap(),
         rpc::Result::success(buffer::get_content::Response {
             content: content.into(),
+            revision: 0,
         })
     );
 }
@@ -179,3 +229,1302 @@ fn buffer_delete_non_existing() {
         Some("unknown_buffer")
     );
 }
+
+fn get_content(client: &mut client::Client, buffer_index: usize) -> String {
+    let mut rpc = client
+        .call(
+            "buffer.get_content",
+            &buffer::get_content::Request {
+                buffer_index: buffer_index,
+            },
+        )
+        .unwrap();
+    let response: buffer::get_content::Response = rpc.wait_for().unwrap();
+    response.content
+}
+
+#[test]
+fn buffer_insert() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("blub\nblah\nbli"));
+
+    let mut rpc = client
+        .call(
+            "buffer.insert",
+            &buffer::insert::Request {
+                buffer_index: 0,
+                position: buffer::Position::LineColumn {
+                    line_index: 1,
+                    column_index: 4,
+                },
+                text: " ünïcode".into(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::insert::Response { revision: 1 })
+    );
+
+    let mut rpc = client
+        .call(
+            "buffer.insert",
+            &buffer::insert::Request {
+                buffer_index: 0,
+                position: buffer::Position::Byte(0),
+                text: "> ".into(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::insert::Response { revision: 2 })
+    );
+
+    assert_eq!("> blub\nblah ünïcode\nbli", get_content(&mut client, 0));
+}
+
+#[test]
+fn buffer_insert_invalid_position() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("ü\nblah"));
+
+    for position in &[
+        buffer::Position::Byte(1),
+        buffer::Position::Byte(100),
+        buffer::Position::LineColumn {
+            line_index: 0,
+            column_index: 2,
+        },
+        buffer::Position::LineColumn {
+            line_index: 5,
+            column_index: 0,
+        },
+    ] {
+        let mut rpc = client
+            .call(
+                "buffer.insert",
+                &buffer::insert::Request {
+                    buffer_index: 0,
+                    position: *position,
+                    text: "x".into(),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            rpc.wait()
+                .unwrap()
+                .unwrap_err()
+                .details
+                .unwrap()
+                .as_str(),
+            Some("invalid_position")
+        );
+    }
+    assert_eq!("ü\nblah", get_content(&mut client, 0));
+}
+
+#[test]
+fn buffer_get_content_bigger_than_frame_limit() {
+    let t = TestHarness::with_config(Config {
+        max_frame_size: 4096,
+        ..Config::default()
+    });
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, None);
+
+    // Every call stays below the limit, only the buffer grows beyond it.
+    let line: String = (0..1023).map(|_| 'x').chain(Some('\n')).collect();
+    for index in 0..10 {
+        insert(&mut client, 0, index * line.len(), &line);
+    }
+    assert_eq!(10 * line.len(), get_content(&mut client, 0).len());
+
+    // The buffer plugin is still there.
+    assert_eq!(11, buffer_info(&mut client, 0).len_lines);
+}
+
+#[test]
+fn buffer_delete_range() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("blub\nblah\nbli"));
+
+    let mut rpc = client
+        .call(
+            "buffer.delete_range",
+            &buffer::delete_range::Request {
+                buffer_index: 0,
+                start: buffer::Position::LineColumn {
+                    line_index: 0,
+                    column_index: 2,
+                },
+                end: buffer::Position::Byte(7),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::delete_range::Response { revision: 1 })
+    );
+    assert_eq!("blah\nbli", get_content(&mut client, 0));
+
+    let mut rpc = client
+        .call(
+            "buffer.delete_range",
+            &buffer::delete_range::Request {
+                buffer_index: 0,
+                start: buffer::Position::Byte(3),
+                end: buffer::Position::Byte(1),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait()
+            .unwrap()
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("invalid_range")
+    );
+}
+
+#[test]
+fn buffer_replace() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("blub\nblah\nbli"));
+
+    let mut rpc = client
+        .call(
+            "buffer.replace",
+            &buffer::replace::Request {
+                buffer_index: 0,
+                start: buffer::Position::LineColumn {
+                    line_index: 1,
+                    column_index: 0,
+                },
+                end: buffer::Position::LineColumn {
+                    line_index: 1,
+                    column_index: 4,
+                },
+                text: "foo\nbar".into(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::replace::Response { revision: 1 })
+    );
+    assert_eq!("blub\nfoo\nbar\nbli", get_content(&mut client, 0));
+}
+
+#[test]
+fn buffer_changed_callback() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let changes = subscribe::<buffer::BufferChanged>(&mut client, "on.buffer.changed");
+    create_buffer(&mut client, 0, Some("blub\nbläh"));
+
+    let mut rpc = client
+        .call(
+            "buffer.replace",
+            &buffer::replace::Request {
+                buffer_index: 0,
+                start: buffer::Position::LineColumn {
+                    line_index: 1,
+                    column_index: 2,
+                },
+                end: buffer::Position::LineColumn {
+                    line_index: 1,
+                    column_index: 4,
+                },
+                text: "ub".into(),
+            },
+        )
+        .unwrap();
+    rpc.wait().unwrap();
+
+    let mut rpc = client
+        .call(
+            "buffer.delete_range",
+            &buffer::delete_range::Request {
+                buffer_index: 0,
+                start: buffer::Position::Byte(0),
+                end: buffer::Position::Byte(5),
+            },
+        )
+        .unwrap();
+    rpc.wait().unwrap();
+
+    assert_eq!(
+        vec![next_event(&changes), next_event(&changes)],
+        vec![
+            buffer::BufferChanged {
+                buffer_index: 0,
+                revision: 1,
+                start: buffer::Location {
+                    byte_index: 7,
+                    char_index: 7,
+                    line_index: 1,
+                    column_index: 2,
+                },
+                end: buffer::Location {
+                    byte_index: 10,
+                    char_index: 9,
+                    line_index: 1,
+                    column_index: 4,
+                },
+                text: "ub".into(),
+            },
+            buffer::BufferChanged {
+                buffer_index: 0,
+                revision: 2,
+                start: buffer::Location {
+                    byte_index: 0,
+                    char_index: 0,
+                    line_index: 0,
+                    column_index: 0,
+                },
+                end: buffer::Location {
+                    byte_index: 5,
+                    char_index: 5,
+                    line_index: 1,
+                    column_index: 0,
+                },
+                text: "".into(),
+            },
+        ]
+    );
+    assert_eq!("blub", get_content(&mut client, 0));
+}
+
+fn insert(client: &mut client::Client, buffer_index: usize, byte_index: usize, text: &str) {
+    let mut rpc = client
+        .call(
+            "buffer.insert",
+            &buffer::insert::Request {
+                buffer_index: buffer_index,
+                position: buffer::Position::Byte(byte_index),
+                text: text.into(),
+            },
+        )
+        .unwrap();
+    assert!(rpc.wait().unwrap().is_ok());
+}
+
+fn call_with_buffer_index(
+    client: &mut client::Client,
+    function: &str,
+    buffer_index: usize,
+) -> rpc::Result {
+    let mut rpc = client
+        .call(
+            function,
+            &buffer::undo::Request {
+                buffer_index: buffer_index,
+            },
+        )
+        .unwrap();
+    rpc.wait().unwrap()
+}
+
+#[test]
+fn buffer_undo_redo() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("blub"));
+
+    insert(&mut client, 0, 4, " blah");
+    insert(&mut client, 0, 0, "> ");
+    assert_eq!("> blub blah", get_content(&mut client, 0));
+
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.undo", 0),
+        rpc::Result::success(buffer::undo::Response { revision: 3 })
+    );
+    assert_eq!("blub blah", get_content(&mut client, 0));
+    call_with_buffer_index(&mut client, "buffer.undo", 0);
+    assert_eq!("blub", get_content(&mut client, 0));
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.undo", 0)
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("nothing_to_undo")
+    );
+
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.redo", 0),
+        rpc::Result::success(buffer::redo::Response { revision: 5 })
+    );
+    assert_eq!("blub blah", get_content(&mut client, 0));
+
+    // Editing after undo starts a new branch, redo follows it.
+    call_with_buffer_index(&mut client, "buffer.undo", 0);
+    insert(&mut client, 0, 0, "# ");
+    call_with_buffer_index(&mut client, "buffer.undo", 0);
+    assert_eq!("blub", get_content(&mut client, 0));
+    call_with_buffer_index(&mut client, "buffer.redo", 0);
+    assert_eq!("# blub", get_content(&mut client, 0));
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.redo", 0)
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("nothing_to_redo")
+    );
+}
+
+#[test]
+fn buffer_undo_transaction() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("blub"));
+
+    insert(&mut client, 0, 4, "\n");
+    assert!(call_with_buffer_index(&mut client, "buffer.begin_transaction", 0).is_ok());
+    insert(&mut client, 0, 0, "a");
+    assert!(call_with_buffer_index(&mut client, "buffer.begin_transaction", 0).is_ok());
+    insert(&mut client, 0, 1, "b");
+    assert!(call_with_buffer_index(&mut client, "buffer.end_transaction", 0).is_ok());
+    insert(&mut client, 0, 2, "c");
+
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.undo", 0)
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("transaction_running")
+    );
+    assert!(call_with_buffer_index(&mut client, "buffer.end_transaction", 0).is_ok());
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.end_transaction", 0)
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("no_transaction")
+    );
+    assert_eq!("abcblub\n", get_content(&mut client, 0));
+
+    call_with_buffer_index(&mut client, "buffer.undo", 0);
+    assert_eq!("blub\n", get_content(&mut client, 0));
+    call_with_buffer_index(&mut client, "buffer.redo", 0);
+    assert_eq!("abcblub\n", get_content(&mut client, 0));
+}
+
+#[test]
+fn buffer_abandoned_transaction_expires() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("blub"));
+
+    // A client that goes away without ending its transaction.
+    let mut rpc = client
+        .call(
+            "buffer.begin_transaction",
+            &buffer::begin_transaction::Request {
+                buffer_index: 0,
+                timeout_ms: Some(100),
+            },
+        )
+        .unwrap();
+    assert!(rpc.wait().unwrap().is_ok());
+    insert(&mut client, 0, 0, "a");
+    insert(&mut client, 0, 1, "b");
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.undo", 0)
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("transaction_running")
+    );
+
+    thread::sleep(time::Duration::from_millis(200));
+    assert!(call_with_buffer_index(&mut client, "buffer.undo", 0).is_ok());
+    assert_eq!("blub", get_content(&mut client, 0));
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.end_transaction", 0)
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("no_transaction")
+    );
+}
+
+#[test]
+fn buffer_save_as_and_save() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let saved = subscribe::<buffer::BufferSaved>(&mut client, "on.buffer.saved");
+    create_buffer(&mut client, 0, Some("blub"));
+
+    let mut rpc = client
+        .call("buffer.save", &buffer::save::Request { buffer_index: 0 })
+        .unwrap();
+    assert_eq!(
+        rpc.wait()
+            .unwrap()
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("no_uri")
+    );
+
+    let path = create_file(&t, "foo", "old content");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+    let uri = format!("file://{}", path.to_str().unwrap());
+
+    let mut rpc = client
+        .call(
+            "buffer.save_as",
+            &buffer::save_as::Request {
+                buffer_index: 0,
+                uri: uri.clone(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::save_as::Response { revision: 0 })
+    );
+    assert_eq!("blub", read_file(&path));
+
+    insert(&mut client, 0, 4, "\nblah");
+    let mut rpc = client
+        .call("buffer.save", &buffer::save::Request { buffer_index: 0 })
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::save::Response { revision: 1 })
+    );
+    assert_eq!("blub\nblah", read_file(&path));
+    assert_eq!(
+        0o640,
+        fs::metadata(&path).unwrap().permissions().mode() & 0o777
+    );
+
+    // No temporary files must be left behind.
+    assert_eq!(
+        vec![path.clone()],
+        fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|p| !p.ends_with("_socket"))
+            .collect::<Vec<_>>()
+    );
+
+    assert_eq!(
+        vec![next_event(&saved), next_event(&saved)],
+        vec![
+            buffer::BufferSaved {
+                buffer_index: 0,
+                uri: uri.clone(),
+                revision: 0,
+            },
+            buffer::BufferSaved {
+                buffer_index: 0,
+                uri: uri,
+                revision: 1,
+            },
+        ]
+    );
+}
+
+#[test]
+fn buffer_save_unhandled_uri() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("blub"));
+
+    let mut rpc = client
+        .call(
+            "buffer.save_as",
+            &buffer::save_as::Request {
+                buffer_index: 0,
+                uri: "blumba://foo".into(),
+            },
+        )
+        .unwrap();
+    assert_eq!(rpc::Result::NotHandled, rpc.wait().unwrap());
+}
+
+fn open_file(client: &mut client::Client, path: &path::Path) -> usize {
+    let mut rpc = client
+        .call(
+            "buffer.open",
+            &buffer::open::Request {
+                uri: format!("file://{}", path.to_str().unwrap()),
+            },
+        )
+        .unwrap();
+    let response: buffer::open::Response = rpc.wait_for().unwrap();
+    response.buffer_index
+}
+
+#[test]
+fn buffer_file_changed_on_disk() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let events =
+        subscribe::<buffer::BufferFileChangedOnDisk>(&mut client, "on.buffer.file_changed_on_disk");
+
+    let path = create_file(&t, "foo", "blub");
+    let uri = format!("file://{}", path.to_str().unwrap());
+    let buffer_index = open_file(&mut client, &path);
+
+    // Our own saves are not reported.
+    insert(&mut client, buffer_index, 4, "\nblah");
+    let mut rpc = client
+        .call(
+            "buffer.save",
+            &buffer::save::Request {
+                buffer_index: buffer_index,
+            },
+        )
+        .unwrap();
+    assert!(rpc.wait().unwrap().is_ok());
+
+    create_file(&t, "foo", "changed by somebody else");
+    assert_eq!(
+        next_event(&events),
+        buffer::BufferFileChangedOnDisk {
+            buffer_index: buffer_index,
+            uri: uri.clone(),
+            deleted: false,
+            reloaded: false,
+        }
+    );
+    assert_eq!("blub\nblah", get_content(&mut client, buffer_index));
+
+    fs::remove_file(&path).unwrap();
+    assert_eq!(
+        next_event(&events),
+        buffer::BufferFileChangedOnDisk {
+            buffer_index: buffer_index,
+            uri: uri,
+            deleted: true,
+            reloaded: false,
+        }
+    );
+}
+
+#[test]
+fn buffer_file_changed_on_disk_auto_reload() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let events =
+        subscribe::<buffer::BufferFileChangedOnDisk>(&mut client, "on.buffer.file_changed_on_disk");
+
+    let path = create_file(&t, "foo", "blub");
+    let buffer_index = open_file(&mut client, &path);
+    let mut rpc = client
+        .call(
+            "buffer.set_auto_reload",
+            &buffer::set_auto_reload::Request {
+                buffer_index: buffer_index,
+                auto_reload: true,
+            },
+        )
+        .unwrap();
+    assert!(rpc.wait().unwrap().is_ok());
+
+    create_file(&t, "foo", "changed by somebody else");
+    assert!(next_event(&events).reloaded);
+    assert_eq!("changed by somebody else", get_content(&mut client, buffer_index));
+
+    // Dirty buffers are never reloaded.
+    insert(&mut client, buffer_index, 0, "unsaved ");
+    create_file(&t, "foo", "changed again");
+    assert!(!next_event(&events).reloaded);
+    assert_eq!(
+        "unsaved changed by somebody else",
+        get_content(&mut client, buffer_index)
+    );
+}
+
+// The server runs in our process, so relative paths start from our working directory.
+fn relative_path(path: &path::Path) -> path::PathBuf {
+    let mut relative = path::PathBuf::new();
+    for _ in env::current_dir().unwrap().components().skip(1) {
+        relative.push("..");
+    }
+    relative.push(path.strip_prefix("/").unwrap());
+    relative
+}
+
+#[test]
+fn buffer_file_changed_on_disk_relative_path() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let events =
+        subscribe::<buffer::BufferFileChangedOnDisk>(&mut client, "on.buffer.file_changed_on_disk");
+
+    let path = create_file(&t, "foo", "blub");
+    let uri = format!(
+        "file://{}",
+        fs::canonicalize(&path).unwrap().to_str().unwrap()
+    );
+    let buffer_index = open_file(&mut client, &relative_path(&path));
+    assert_eq!(
+        Some(uri.clone()),
+        buffer_info(&mut client, buffer_index).uri
+    );
+
+    create_file(&t, "foo", "changed by somebody else");
+    assert_eq!(uri, next_event(&events).uri);
+}
+
+#[test]
+fn buffer_file_changed_on_disk_through_symlink() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let events =
+        subscribe::<buffer::BufferFileChangedOnDisk>(&mut client, "on.buffer.file_changed_on_disk");
+
+    let directory = t.temp_directory.path().join("real");
+    fs::create_dir(&directory).unwrap();
+    let target = directory.join("foo");
+    fs::File::create(&target)
+        .unwrap()
+        .write_all(b"blub")
+        .unwrap();
+    let link = t.temp_directory.path().join("link");
+    ::std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    let uri = format!(
+        "file://{}",
+        fs::canonicalize(&target).unwrap().to_str().unwrap()
+    );
+    let buffer_index = open_file(&mut client, &link);
+    assert_eq!(
+        Some(uri.clone()),
+        buffer_info(&mut client, buffer_index).uri
+    );
+
+    fs::File::create(&target)
+        .unwrap()
+        .write_all(b"changed by somebody else")
+        .unwrap();
+    assert_eq!(uri, next_event(&events).uri);
+}
+
+fn buffer_info(client: &mut client::Client, buffer_index: usize) -> buffer::info::Response {
+    let mut rpc = client
+        .call(
+            "buffer.info",
+            &buffer::info::Request {
+                buffer_index: buffer_index,
+            },
+        )
+        .unwrap();
+    rpc.wait_for().unwrap()
+}
+
+#[test]
+fn buffer_open_encodings_round_trip() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    let files: Vec<(Vec<u8>, buffer::Encoding, bool, buffer::LineEnding)> = vec![
+        (
+            b"bl\xc3\xa4h\nblub".to_vec(),
+            buffer::Encoding::Utf8,
+            false,
+            buffer::LineEnding::Lf,
+        ),
+        (
+            b"\xef\xbb\xbfbl\xc3\xa4h\r\nblub".to_vec(),
+            buffer::Encoding::Utf8,
+            true,
+            buffer::LineEnding::CrLf,
+        ),
+        (
+            b"bl\xe4h\nblub".to_vec(),
+            buffer::Encoding::Latin1,
+            false,
+            buffer::LineEnding::Lf,
+        ),
+        (
+            b"\xff\xfeb\0l\0\xe4\0h\0\r\0\n\0b\0l\0u\0b\0".to_vec(),
+            buffer::Encoding::Utf16Le,
+            true,
+            buffer::LineEnding::CrLf,
+        ),
+        (
+            b"\xfe\xff\0b\0l\0\xe4\0h\0\n\0b\0l\0u\0b".to_vec(),
+            buffer::Encoding::Utf16Be,
+            true,
+            buffer::LineEnding::Lf,
+        ),
+        (
+            b"b\0l\0\xe4\0h\0\n\0b\0l\0u\0b\0".to_vec(),
+            buffer::Encoding::Utf16Le,
+            false,
+            buffer::LineEnding::Lf,
+        ),
+    ];
+
+    for (index, &(ref data, encoding, bom, line_ending)) in files.iter().enumerate() {
+        let mut path = t.temp_directory.path().to_path_buf();
+        path.push(format!("file_{}", index));
+        fs::File::create(&path).unwrap().write_all(data).unwrap();
+
+        let buffer_index = open_file(&mut client, &path);
+        assert_eq!("bläh\nblub", get_content(&mut client, buffer_index));
+        let info = buffer_info(&mut client, buffer_index);
+        assert_eq!(
+            buffer::Format {
+                encoding: encoding,
+                bom: bom,
+                line_ending: line_ending,
+            },
+            info.format
+        );
+        assert!(!info.dirty);
+        assert_eq!(2, info.len_lines);
+
+        insert(&mut client, buffer_index, 0, "x");
+        assert!(buffer_info(&mut client, buffer_index).dirty);
+        call_with_buffer_index(&mut client, "buffer.undo", buffer_index);
+        assert!(!buffer_info(&mut client, buffer_index).dirty);
+
+        let mut rpc = client
+            .call(
+                "buffer.save",
+                &buffer::save::Request {
+                    buffer_index: buffer_index,
+                },
+            )
+            .unwrap();
+        assert!(rpc.wait().unwrap().is_ok());
+        let mut saved = Vec::new();
+        fs::File::open(&path)
+            .unwrap()
+            .read_to_end(&mut saved)
+            .unwrap();
+        assert_eq!(*data, saved);
+    }
+}
+
+#[test]
+fn buffer_save_unencodable_character() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    let mut path = t.temp_directory.path().to_path_buf();
+    path.push("latin1");
+    fs::File::create(&path)
+        .unwrap()
+        .write_all(b"bl\xe4h")
+        .unwrap();
+    let buffer_index = open_file(&mut client, &path);
+    insert(&mut client, buffer_index, 0, "€");
+
+    let mut rpc = client
+        .call(
+            "buffer.save",
+            &buffer::save::Request {
+                buffer_index: buffer_index,
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap().unwrap_err().details.unwrap().as_str(),
+        Some("invalid_data")
+    );
+    assert_eq!(b"bl\xe4h".to_vec(), {
+        let mut saved = Vec::new();
+        fs::File::open(&path)
+            .unwrap()
+            .read_to_end(&mut saved)
+            .unwrap();
+        saved
+    });
+}
+
+fn open_uri(client: &mut client::Client, uri: &str) -> usize {
+    let mut rpc = client
+        .call("buffer.open", &buffer::open::Request { uri: uri.into() })
+        .unwrap();
+    let response: buffer::open::Response = rpc.wait_for().unwrap();
+    response.buffer_index
+}
+
+#[test]
+fn buffer_open_untitled() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    let first = open_uri(&mut client, "untitled:");
+    let second = open_uri(&mut client, "untitled:");
+    assert!(first != second);
+    assert_eq!(
+        Some("untitled:0".into()),
+        buffer_info(&mut client, first).uri
+    );
+    assert_eq!("", get_content(&mut client, first));
+
+    let named = open_uri(&mut client, "untitled:notes");
+    assert_eq!(named, open_uri(&mut client, "untitled:notes"));
+
+    insert(&mut client, named, 0, "blub");
+    assert!(buffer_info(&mut client, named).dirty);
+
+    // There is no file to write to, this needs 'buffer.save_as'.
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.save", named)
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("no_uri")
+    );
+    assert!(buffer_info(&mut client, named).dirty);
+}
+
+#[test]
+fn buffer_open_scratch() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    let buffer_index = open_uri(&mut client, "scratch:");
+    insert(&mut client, buffer_index, 0, "blub");
+    let info = buffer_info(&mut client, buffer_index);
+    assert!(!info.dirty);
+    assert_eq!(1, info.revision);
+
+    assert_eq!(
+        call_with_buffer_index(&mut client, "buffer.save", buffer_index)
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("no_uri")
+    );
+}
+
+#[test]
+fn buffer_open_third_party_scheme() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    let mut plugin_client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let thin_client = Mutex::new(plugin_client.clone().unwrap());
+    plugin_client
+        .new_rpc(
+            "buffer.open",
+            Box::new(CallbackRpc {
+                priority: 50,
+                callback: move |mut context: client::rpc::server::Context,
+                                args: serde_json::Value| {
+                    let request: buffer::open::Request = serde_json::from_value(args).unwrap();
+                    if !request.uri.starts_with("zip://") {
+                        context.finish(rpc::Result::NotHandled).unwrap();
+                        return;
+                    }
+                    let mut rpc = thin_client
+                        .lock()
+                        .unwrap()
+                        .call(
+                            "buffer.new",
+                            &buffer::new::Request {
+                                content: Some("unpacked".into()),
+                                uri: Some(request.uri),
+                            },
+                        )
+                        .unwrap();
+                    let response: buffer::new::Response = rpc.wait_for().unwrap();
+                    context
+                        .finish(rpc::Result::success(buffer::open::Response {
+                            buffer_index: response.buffer_index,
+                        }))
+                        .unwrap();
+                },
+            }),
+        )
+        .unwrap();
+
+    let buffer_index = open_uri(&mut client, "zip://archive.zip:foo.txt");
+    assert_eq!("unpacked", get_content(&mut client, buffer_index));
+    assert_eq!(
+        Some("zip://archive.zip:foo.txt".into()),
+        buffer_info(&mut client, buffer_index).uri
+    );
+
+    // Everything else still ends up in the buffer plugin.
+    let buffer_index = open_uri(&mut client, "untitled:foo");
+    assert_eq!(
+        Some("untitled:foo".into()),
+        buffer_info(&mut client, buffer_index).uri
+    );
+}
+
+#[test]
+fn buffer_get_lines() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("one\ntwö\r\nthree\n"));
+
+    let get_lines = |client: &mut client::Client, first: usize, count: usize| {
+        let mut rpc = client
+            .call(
+                "buffer.get_lines",
+                &buffer::get_lines::Request {
+                    buffer_index: 0,
+                    first: first,
+                    count: count,
+                },
+            )
+            .unwrap();
+        rpc.wait().unwrap()
+    };
+
+    assert_eq!(
+        get_lines(&mut client, 1, 2),
+        rpc::Result::success(buffer::get_lines::Response {
+            lines: vec!["twö".into(), "three".into()],
+            revision: 0,
+            len_lines: 4,
+        })
+    );
+    assert_eq!(
+        get_lines(&mut client, 2, 100),
+        rpc::Result::success(buffer::get_lines::Response {
+            lines: vec!["three".into(), "".into()],
+            revision: 0,
+            len_lines: 4,
+        })
+    );
+    assert_eq!(
+        get_lines(&mut client, 4, 1),
+        rpc::Result::success(buffer::get_lines::Response {
+            lines: vec![],
+            revision: 0,
+            len_lines: 4,
+        })
+    );
+    assert_eq!(
+        get_lines(&mut client, 5, 1)
+            .unwrap_err()
+            .details
+            .unwrap()
+            .as_str(),
+        Some("invalid_position")
+    );
+}
+
+#[test]
+fn buffer_only_breaks_lines_at_newline() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(
+        &mut client,
+        0,
+        Some("one\x0ctwo\rthree\u{2028}four\u{85}five\nsix"),
+    );
+
+    let mut rpc = client
+        .call(
+            "buffer.get_lines",
+            &buffer::get_lines::Request {
+                buffer_index: 0,
+                first: 0,
+                count: 10,
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::get_lines::Response {
+            lines: vec![
+                "one\x0ctwo\rthree\u{2028}four\u{85}five".into(),
+                "six".into(),
+            ],
+            revision: 0,
+            len_lines: 2,
+        })
+    );
+
+    let mut rpc = client
+        .call(
+            "buffer.insert",
+            &buffer::insert::Request {
+                buffer_index: 0,
+                position: buffer::Position::LineColumn {
+                    line_index: 0,
+                    column_index: 4,
+                },
+                text: "ü".into(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::insert::Response { revision: 1 })
+    );
+    assert_eq!(
+        "one\x0cütwo\rthree\u{2028}four\u{85}five\nsix",
+        get_content(&mut client, 0)
+    );
+}
+
+#[test]
+fn buffer_get_range() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    create_buffer(&mut client, 0, Some("bläh\nblub"));
+
+    let get_range = |client: &mut client::Client, start, end| {
+        let mut rpc = client
+            .call(
+                "buffer.get_range",
+                &buffer::get_range::Request {
+                    buffer_index: 0,
+                    start: start,
+                    end: end,
+                },
+            )
+            .unwrap();
+        rpc.wait().unwrap()
+    };
+
+    assert_eq!(
+        get_range(
+            &mut client,
+            buffer::Position::Char(2),
+            buffer::Position::LineColumn {
+                line_index: 1,
+                column_index: 2,
+            }
+        ),
+        rpc::Result::success(buffer::get_range::Response {
+            text: "äh\nbl".into(),
+            start: buffer::Location {
+                byte_index: 2,
+                char_index: 2,
+                line_index: 0,
+                column_index: 2,
+            },
+            end: buffer::Location {
+                byte_index: 8,
+                char_index: 7,
+                line_index: 1,
+                column_index: 2,
+            },
+            revision: 0,
+            len_bytes: 10,
+            len_lines: 2,
+        })
+    );
+    assert_eq!(
+        get_range(
+            &mut client,
+            buffer::Position::Byte(3),
+            buffer::Position::Byte(4)
+        )
+        .unwrap_err()
+        .details
+        .unwrap()
+        .as_str(),
+        Some("invalid_position")
+    );
+    assert_eq!(
+        get_range(
+            &mut client,
+            buffer::Position::Char(3),
+            buffer::Position::Char(10)
+        )
+        .unwrap_err()
+        .details
+        .unwrap()
+        .as_str(),
+        Some("invalid_position")
+    );
+}
+
+fn create_large_file(t: &TestHarness, name: &str, num_lines: usize) -> path::PathBuf {
+    let mut path = t.temp_directory.path().to_path_buf();
+    path.push(name);
+    let mut content = String::new();
+    for line_index in 0..num_lines {
+        content.push_str(&format!("{:063}\n", line_index));
+    }
+    fs::File::create(&path)
+        .unwrap()
+        .write_all(content.as_bytes())
+        .unwrap();
+    path
+}
+
+#[test]
+fn buffer_open_large_file_streams_progress() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    // 2 MiB, so that it is above the streaming threshold.
+    let num_lines = 32768;
+    let path = create_large_file(&t, "large", num_lines);
+
+    let mut rpc = client
+        .call(
+            "buffer.open",
+            &buffer::open::Request {
+                uri: format!("file://{}", path.to_str().unwrap()),
+            },
+        )
+        .unwrap();
+
+    let updates: Vec<buffer::open::Progress> =
+        rpc.updates().collect::<swiboe::Result<_>>().unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::open::Response { buffer_index: 0 })
+    );
+
+    assert!(updates.len() > 2);
+    assert_eq!(0, updates[0].bytes_loaded);
+    for pair in updates.windows(2) {
+        assert!(pair[0].bytes_loaded < pair[1].bytes_loaded);
+    }
+    let last = updates.last().unwrap();
+    assert_eq!(2 * 1024 * 1024, last.total_bytes);
+    assert_eq!(last.total_bytes, last.bytes_loaded);
+    assert_eq!(num_lines, last.lines_indexed);
+
+    let info = buffer_info(&mut client, 0);
+    assert!(!info.loading);
+    assert!(!info.dirty);
+    assert_eq!(num_lines + 1, info.len_lines);
+
+    let mut rpc = client
+        .call(
+            "buffer.get_lines",
+            &buffer::get_lines::Request {
+                buffer_index: 0,
+                first: 1000,
+                count: 1,
+            },
+        )
+        .unwrap();
+    let response: buffer::get_lines::Response = rpc.wait_for().unwrap();
+    assert_eq!(vec![format!("{:063}", 1000)], response.lines);
+}
+
+#[test]
+fn buffer_open_large_utf16_file_counts_decoded_lines() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+
+    // Every '\u{a00}' has a '\n' byte in UTF-16, but is no line break.
+    let num_lines = 8192;
+    let line = format!("{}\n", "\u{a00}".repeat(63));
+    let mut content = vec![0xff, 0xfe];
+    for _ in 0..num_lines {
+        for unit in line.encode_utf16() {
+            content.push(unit as u8);
+            content.push((unit >> 8) as u8);
+        }
+    }
+    let mut path = t.temp_directory.path().to_path_buf();
+    path.push("large_utf16");
+    fs::File::create(&path)
+        .unwrap()
+        .write_all(&content)
+        .unwrap();
+
+    let mut rpc = client
+        .call(
+            "buffer.open",
+            &buffer::open::Request {
+                uri: format!("file://{}", path.to_str().unwrap()),
+            },
+        )
+        .unwrap();
+    let updates: Vec<buffer::open::Progress> =
+        rpc.updates().collect::<swiboe::Result<_>>().unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::open::Response { buffer_index: 0 })
+    );
+
+    assert_eq!(num_lines, updates.last().unwrap().lines_indexed);
+    assert_eq!(num_lines + 1, buffer_info(&mut client, 0).len_lines);
+}
+
+#[test]
+fn buffer_open_file_bigger_than_frame_limit() {
+    let t = TestHarness::with_config(Config {
+        max_frame_size: 1 << 20,
+        ..Config::default()
+    });
+    let mut client =
+        client::Client::connect_unix_with_max_frame_size(&t.socket_name, 1 << 20).unwrap();
+    let reloaded = subscribe::<buffer::BufferReloaded>(&mut client, "on.buffer.reloaded");
+    let changes = subscribe::<buffer::BufferChanged>(&mut client, "on.buffer.changed");
+
+    // 2 MiB, which would not fit into a single frame.
+    let path = create_large_file(&t, "large", 32768);
+    let mut rpc = client
+        .call(
+            "buffer.open",
+            &buffer::open::Request {
+                uri: format!("file://{}", path.to_str().unwrap()),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        rpc.wait().unwrap(),
+        rpc::Result::success(buffer::open::Response { buffer_index: 0 })
+    );
+
+    assert_eq!(
+        buffer::BufferReloaded {
+            buffer_index: 0,
+            revision: 1,
+        },
+        next_event(&reloaded)
+    );
+    assert!(changes.try_recv().is_err());
+
+    // Both the client and the buffer plugin are still connected.
+    let info = buffer_info(&mut client, 0);
+    assert!(!info.loading);
+    assert_eq!(32769, info.len_lines);
+}
+
+#[test]
+fn buffer_open_large_file_cancelled() {
+    let t = TestHarness::new();
+    let mut client = client::Client::connect_unix(&t.socket_name).unwrap();
+    let deleted = subscribe::<serde_json::Value>(&mut client, "on.buffer.deleted");
+
+    let path = create_large_file(&t, "large", 262144);
+
+    let mut rpc = client
+        .call(
+            "buffer.open",
+            &buffer::open::Request {
+                uri: format!("file://{}", path.to_str().unwrap()),
+            },
+        )
+        .unwrap();
+    let progress: buffer::open::Progress = rpc.updates().next().unwrap().unwrap();
+    assert_eq!(0, progress.buffer_index);
+    rpc.cancel().unwrap();
+
+    assert_eq!(0, next_event(&deleted)["buffer_index"]);
+    let mut rpc = client.call("buffer.list", &buffer::list::Request).unwrap();
+    let response: buffer::list::Response = rpc.wait_for().unwrap();
+    assert!(response.buffer_indices.is_empty());
+}
@@ -2,9 +2,13 @@
 // Licensed under the Apache License, Version 2.0. See LICENSE.txt
 // in the project root for license information.
 
+extern crate rcgen;
+extern crate rmp_serde;
 extern crate serde;
 extern crate serde_json;
 extern crate swiboe;
+extern crate tempdir;
+extern crate unix_socket;
 extern crate uuid;
 
 use std::fs;
@@ -14,6 +18,7 @@ use swiboe::client;
 use swiboe::testing;
 
 mod core;
+mod ipc;
 mod plugin_buffer;
 
 pub struct CallbackRpc<F> {